@@ -0,0 +1,203 @@
+CREATE TABLE IF NOT EXISTS memes (
+    id SERIAL PRIMARY KEY,
+    image_url TEXT NOT NULL
+);
+
+CREATE TABLE users (
+    id VARCHAR(32) PRIMARY KEY,
+    is_admin BOOLEAN DEFAULT FALSE,
+    username VARCHAR(32) NOT NULL
+);
+
+CREATE TABLE likes (
+    meme_id INTEGER NOT NULL,
+    user_id VARCHAR(32) NOT NULL,
+    PRIMARY KEY (user_id, meme_id)
+);
+
+CREATE TABLE saved (
+    meme_id INTEGER NOT NULL,
+    user_id VARCHAR(32) NOT NULL,
+    PRIMARY KEY (user_id, meme_id)
+);
+
+CREATE TABLE comments (
+    id SERIAL PRIMARY KEY,
+    meme_id INTEGER NOT NULL,
+    user_id VARCHAR(32) NOT NULL,
+    content VARCHAR(128) NOT NULL,
+    CONSTRAINT fk_comment_meme FOREIGN KEY (meme_id) REFERENCES memes(id) ON DELETE CASCADE,
+    CONSTRAINT fk_comment_user FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_likes_meme_id ON likes(meme_id);
+
+ALTER TABLE memes ADD COLUMN like_count INTEGER DEFAULT 0;
+
+ALTER TABLE memes ALTER COLUMN like_count SET NOT NULL;
+
+ALTER TABLE likes
+ADD CONSTRAINT fk_meme FOREIGN KEY (meme_id) REFERENCES memes(id) ON DELETE CASCADE,
+ADD CONSTRAINT fk_user FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE users ADD CONSTRAINT unique_username UNIQUE (username);
+
+ALTER TABLE memes
+ADD COLUMN created_by VARCHAR(32),
+ADD CONSTRAINT fk_created_by
+	FOREIGN KEY (created_by)
+	REFERENCES users(id)
+	ON DELETE SET NULL;
+
+-- memes uploaded before they had an owner, set it to the admin's user id
+-- UPDATE memes SET created_by = '<sudo_id>' WHERE created_by IS NULL;
+
+ALTER TABLE memes
+ALTER COLUMN created_by SET NOT NULL;
+
+CREATE INDEX idx_saved_memes_user_id ON saved(user_id);
+CREATE INDEX idx_saved_memes_meme_id ON saved(meme_id);
+
+ALTER TABLE saved
+ADD CONSTRAINT fk_saved_meme FOREIGN KEY (meme_id) REFERENCES memes(id) ON DELETE CASCADE,
+ADD CONSTRAINT fk_saved_user FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+CREATE TABLE meme_renditions (
+    meme_id INTEGER NOT NULL,
+    name VARCHAR(16) NOT NULL,
+    image_url TEXT NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    PRIMARY KEY (meme_id, name),
+    CONSTRAINT fk_rendition_meme FOREIGN KEY (meme_id) REFERENCES memes(id) ON DELETE CASCADE
+);
+
+ALTER TABLE memes
+ADD COLUMN phash BIGINT,
+ADD COLUMN duplicate_of INTEGER,
+ADD CONSTRAINT fk_duplicate_of
+	FOREIGN KEY (duplicate_of)
+	REFERENCES memes(id)
+	ON DELETE SET NULL;
+
+ALTER TABLE memes
+ADD COLUMN phash_bands SMALLINT[] GENERATED ALWAYS AS (ARRAY[
+    (phash & 255)::smallint,
+    (256 + ((phash >> 8) & 255))::smallint,
+    (512 + ((phash >> 16) & 255))::smallint,
+    (768 + ((phash >> 24) & 255))::smallint,
+    (1024 + ((phash >> 32) & 255))::smallint,
+    (1280 + ((phash >> 40) & 255))::smallint,
+    (1536 + ((phash >> 48) & 255))::smallint,
+    (1792 + ((phash >> 56) & 255))::smallint
+]) STORED;
+
+CREATE INDEX idx_memes_phash_bands ON memes USING GIN (phash_bands);
+
+CREATE TABLE pending_deletions (
+    id SERIAL PRIMARY KEY,
+    object_key TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_pending_deletions_due ON pending_deletions(next_attempt_at) WHERE completed_at IS NULL;
+
+ALTER TABLE memes ADD COLUMN object_key TEXT;
+ALTER TABLE meme_renditions ADD COLUMN object_key TEXT;
+
+-- existing objects keep their timestamp names, only new uploads get content addressed keys
+UPDATE memes SET object_key = regexp_replace(image_url, '^.*/', '') WHERE object_key IS NULL;
+UPDATE meme_renditions SET object_key = regexp_replace(image_url, '^.*/', '') WHERE object_key IS NULL;
+
+ALTER TABLE memes ALTER COLUMN object_key SET NOT NULL;
+ALTER TABLE meme_renditions ALTER COLUMN object_key SET NOT NULL;
+
+CREATE INDEX idx_memes_object_key ON memes(object_key);
+CREATE INDEX idx_meme_renditions_object_key ON meme_renditions(object_key);
+
+-- null until `backfill-metadata` has been run for memes uploaded before these existed
+ALTER TABLE memes
+ADD COLUMN width INTEGER,
+ADD COLUMN height INTEGER,
+ADD COLUMN mime_type VARCHAR(32),
+ADD COLUMN byte_size BIGINT,
+ADD COLUMN is_animated BOOLEAN;
+
+-- null until `backfill-blurhash` has been run
+ALTER TABLE memes ADD COLUMN blurhash VARCHAR(64);
+
+ALTER TABLE memes
+ADD COLUMN media_type VARCHAR(8) NOT NULL DEFAULT 'image',
+ADD COLUMN poster_url TEXT;
+
+-- renditions can exist in more than one format
+ALTER TABLE meme_renditions ADD COLUMN mime_type VARCHAR(32);
+
+UPDATE meme_renditions
+SET mime_type = CASE
+	WHEN object_key LIKE '%.gif' THEN 'image/gif'
+	WHEN object_key LIKE '%.mp4' THEN 'video/mp4'
+	WHEN object_key LIKE '%.webm' THEN 'video/webm'
+	ELSE 'image/webp'
+END
+WHERE mime_type IS NULL;
+
+ALTER TABLE meme_renditions
+ALTER COLUMN mime_type SET NOT NULL,
+DROP CONSTRAINT meme_renditions_pkey,
+ADD PRIMARY KEY (meme_id, name, mime_type);
+
+-- WebP quality picked for each meme, for tuning COMPRESSION_MODE
+ALTER TABLE memes ADD COLUMN compression_quality REAL;
+
+CREATE TABLE sessions (
+    id VARCHAR(32) PRIMARY KEY,
+    user_id VARCHAR(32) NOT NULL,
+    refresh_token_hash VARCHAR(64) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ,
+    CONSTRAINT fk_session_user FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_sessions_user_id ON sessions(user_id);
+
+ALTER TABLE sessions
+ADD COLUMN last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+ADD COLUMN user_agent VARCHAR(256),
+ADD COLUMN ip VARCHAR(64);
+
+-- accounts users sign in with, existing users were all created from their Google id
+CREATE TABLE identities (
+    provider VARCHAR(32) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    user_id VARCHAR(32) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (provider, subject),
+    CONSTRAINT fk_identity_user FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_identities_user_id ON identities(user_id);
+
+INSERT INTO identities (provider, subject, user_id)
+SELECT 'google', id, id FROM users
+ON CONFLICT DO NOTHING;
+
+-- completed deletions are pruned after DELETIONS_RETENTION days
+CREATE INDEX idx_pending_deletions_completed ON pending_deletions(completed_at) WHERE completed_at IS NOT NULL;
+
+-- the hash a session was last rotated from, only presenting that one revokes the session
+ALTER TABLE sessions ADD COLUMN previous_refresh_token_hash VARCHAR(64);
+
+-- when the refresh token was last rotated, the previous one is let through as a concurrent refresh shortly after
+ALTER TABLE sessions ADD COLUMN rotated_at TIMESTAMPTZ;
+
+-- video posters were stored under the names of image renditions
+UPDATE meme_renditions
+SET name = 'poster_' || name
+WHERE name IN ('thumbnail', 'feed')
+AND meme_id IN (SELECT id FROM memes WHERE media_type = 'video');
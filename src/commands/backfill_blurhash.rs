@@ -0,0 +1,64 @@
+use crate::models;
+use memelibre_server::processing::placeholder;
+
+const BATCH_SIZE: i64 = 100;
+
+/// `backfill-blurhash`, computes the placeholder of memes uploaded before it was stored
+pub async fn run(_args: &[String], state: &models::AppState) -> Result<(), String> {
+    let mut last_id = 0;
+    let mut updated = 0;
+    let mut failed = 0;
+
+    loop {
+        let memes: Vec<(i32, String)> = sqlx::query_as(
+            "
+            SELECT id, object_key
+            FROM memes
+            WHERE blurhash IS NULL AND media_type = 'image' AND id > $1
+            ORDER BY id ASC
+            LIMIT $2
+            ",
+        )
+        .bind(last_id)
+        .bind(BATCH_SIZE)
+        .fetch_all(&state.db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let Some(&(id, _)) = memes.last() else {
+            break;
+        };
+        last_id = id;
+
+        for (id, object_key) in memes {
+            let blurhash = state
+                .storage
+                .get(&object_key)
+                .await
+                .and_then(|data| placeholder::from_stored(&data, &state.config));
+
+            let blurhash = match blurhash {
+                Ok(blurhash) => blurhash,
+                Err(e) => {
+                    eprintln!("Failed to read meme {} ({}): {}", id, object_key, e);
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            sqlx::query("UPDATE memes SET blurhash = $1 WHERE id = $2")
+                .bind(blurhash)
+                .bind(id)
+                .execute(&state.db)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            updated += 1;
+        }
+    }
+
+    println!("updated memes: {}", updated);
+    println!("failed memes: {}", failed);
+
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use crate::models;
+use memelibre_server::processing;
+
+const BATCH_SIZE: i64 = 100;
+
+/// `backfill-metadata`, records the format and size of memes uploaded before they were stored
+pub async fn run(_args: &[String], state: &models::AppState) -> Result<(), String> {
+    let mut last_id = 0;
+    let mut updated = 0;
+    let mut failed = 0;
+
+    loop {
+        let memes: Vec<(i32, String)> = sqlx::query_as(
+            "
+            SELECT id, object_key
+            FROM memes
+            WHERE width IS NULL AND id > $1
+            ORDER BY id ASC
+            LIMIT $2
+            ",
+        )
+        .bind(last_id)
+        .bind(BATCH_SIZE)
+        .fetch_all(&state.db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let Some(&(id, _)) = memes.last() else {
+            break;
+        };
+        last_id = id;
+
+        for (id, object_key) in memes {
+            let probed = state
+                .storage
+                .get(&object_key)
+                .await
+                .and_then(|data| Ok((data.len(), processing::probe(&data)?)));
+
+            let (byte_size, probe) = match probed {
+                Ok(probed) => probed,
+                Err(e) => {
+                    eprintln!("Failed to read meme {} ({}): {}", id, object_key, e);
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            sqlx::query(
+                "
+                UPDATE memes
+                SET byte_size = $1, height = $2, is_animated = $3, mime_type = $4, width = $5
+                WHERE id = $6
+                ",
+            )
+            .bind(byte_size as i64)
+            .bind(probe.height as i32)
+            .bind(probe.animated)
+            .bind(probe.content_type)
+            .bind(probe.width as i32)
+            .bind(id)
+            .execute(&state.db)
+            .await
+            .map_err(|e| e.to_string())?;
+
+            updated += 1;
+        }
+    }
+
+    println!("updated memes: {}", updated);
+    println!("failed memes: {}", failed);
+
+    Ok(())
+}
@@ -0,0 +1,14 @@
+use crate::models;
+
+pub mod backfill_blurhash;
+pub mod backfill_metadata;
+pub mod reconcile;
+
+pub async fn run(command: &str, args: &[String], state: &models::AppState) -> Result<(), String> {
+    match command {
+        "backfill-blurhash" => backfill_blurhash::run(args, state).await,
+        "backfill-metadata" => backfill_metadata::run(args, state).await,
+        "reconcile" => reconcile::run(args, state).await,
+        _ => Err(format!("Unknown command: {}", command)),
+    }
+}
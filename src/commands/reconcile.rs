@@ -0,0 +1,32 @@
+use crate::models;
+use chrono::Duration;
+use memelibre_server::reconcile;
+
+/// `reconcile [--delete]`, reports mismatches between the database and storage
+pub async fn run(args: &[String], state: &models::AppState) -> Result<(), String> {
+    let delete = args.iter().any(|arg| arg == "--delete");
+
+    let report = reconcile::run(
+        &state.db,
+        state.storage.as_ref(),
+        Duration::seconds(state.config.reconcile_grace_period as i64),
+        delete,
+    )
+    .await?;
+
+    println!("missing objects: {}", report.missing.len());
+    for key in &report.missing {
+        println!("  {}", key);
+    }
+
+    println!("orphaned objects: {}", report.orphaned.len());
+    for key in &report.orphaned {
+        println!("  {}", key);
+    }
+
+    if delete {
+        println!("deleted objects: {}", report.deleted.len());
+    }
+
+    Ok(())
+}
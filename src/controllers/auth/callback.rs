@@ -1,16 +1,22 @@
+use crate::controllers::auth::cookies;
 use crate::http_error;
 use crate::models;
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
+    extract::{ConnectInfo, Extension, Query, State},
+    http::{HeaderMap, StatusCode},
     response::Redirect,
 };
-use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
-use chrono;
-use jsonwebtoken::{encode, EncodingKey, Header};
+use axum_extra::extract::cookie::{Cookie, CookieJar};
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use memelibre_server::{oauth::ExchangeError, sessions};
+use rand::distr::Alphanumeric;
+use rand::{rng, Rng};
 use serde::Deserialize;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
+const MAX_USERNAME_ATTEMPTS: usize = 10;
+
 #[derive(Deserialize)]
 pub struct OAuthCallback {
     code: Option<String>,
@@ -18,185 +24,224 @@ pub struct OAuthCallback {
     state: Option<String>,
 }
 
-#[derive(Deserialize)]
-struct UserInfo {
-    id: String,
+fn generate_user_id() -> String {
+    rng()
+        .sample_iter(Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
 }
 
-async fn exchange_code_for_token(
+/// Whoever is already signed in when coming back from a provider gets the identity linked
+async fn signed_in_user_id(
     state: &models::AppState,
-    client: &reqwest::Client,
-    auth_code: &str,
-) -> Result<models::TokenResponse, (StatusCode, String)> {
-    let params = vec![
-        ("client_id", state.config.oauth_google_client_id.as_str()),
-        (
-            "client_secret",
-            state.config.oauth_google_client_secret.as_str(),
-        ),
-        ("code", auth_code),
-        ("grant_type", "authorization_code"),
-        ("redirect_uri", state.config.oauth_redirect_uri.as_str()),
-    ];
-
-    let response = client
-        .post("https://oauth2.googleapis.com/token")
-        .form(&params)
-        .send()
-        .await
-        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+    jar: &CookieJar,
+) -> Result<Option<String>, (StatusCode, String)> {
+    let Some(session_token) = jar.get("session_token") else {
+        return Ok(None);
+    };
 
-    if !response.status().is_success() {
-        let e = response
-            .text()
-            .await
-            .unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e));
-    }
+    let Ok(token) = decode::<models::JWTClaims>(
+        session_token.value(),
+        &DecodingKey::from_secret(state.config.jwt_secret.as_bytes()),
+        &Validation::new(Algorithm::HS256),
+    ) else {
+        return Ok(None);
+    };
 
-    let token_response: models::TokenResponse = response
-        .json()
+    let is_active = sessions::touch(&state.db, &token.claims.sid)
         .await
         .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
 
-    Ok(token_response)
+    Ok(is_active.then_some(token.claims.sub))
 }
 
-async fn get_user_info(
-    client: &reqwest::Client,
-    access_token: &str,
-) -> Result<UserInfo, (StatusCode, String)> {
-    let response = client
-        .get("https://www.googleapis.com/oauth2/v2/userinfo")
-        .bearer_auth(access_token)
-        .send()
-        .await
-        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+async fn find_or_create_user(
+    state: &models::AppState,
+    provider: &str,
+    subject: &str,
+    signed_in_user_id: Option<String>,
+) -> Result<models::User, (StatusCode, String)> {
+    let existing_user: Option<models::User> = sqlx::query_as(
+        "
+        SELECT users.id, users.is_admin, users.username
+        FROM identities
+        JOIN users ON users.id = identities.user_id
+        WHERE identities.provider = $1 AND identities.subject = $2
+        ",
+    )
+    .bind(provider)
+    .bind(subject)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
 
-    if !response.status().is_success() {
-        let e = response
-            .text()
-            .await
-            .unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e));
+    if let Some(user) = existing_user {
+        return Ok(user);
     }
 
-    let user_info: UserInfo = response
-        .json()
+    let mut tx = state
+        .db
+        .begin()
         .await
         .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
 
-    Ok(user_info)
-}
-
-async fn create_user_session(
-    state: &models::AppState,
-    user_info: &UserInfo,
-) -> Result<String, (StatusCode, String)> {
-    let existing_user: Option<models::User> =
-        sqlx::query_as("SELECT id, is_admin, username FROM users WHERE id = $1")
-            .bind(&user_info.id)
-            .fetch_optional(&state.db)
+    let user: models::User = match signed_in_user_id {
+        Some(user_id) => sqlx::query_as("SELECT id, is_admin, username FROM users WHERE id = $1")
+            .bind(&user_id)
+            .fetch_one(&mut *tx)
             .await
-            .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
-
-    let user = match existing_user {
-        Some(user) => user,
+            .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?,
         None => {
-            let mut generated_username = memelibre_server::generate_username()?;
+            let mut attempts = 0;
 
+            // Generated usernames can collide with taken ones, also with one taken by a
+            // concurrent login between a check and the insert, so the constraint decides
             loop {
-                let existing_username: Option<(String,)> =
-                    sqlx::query_as("SELECT username FROM users WHERE username = $1")
-                        .bind(&generated_username)
-                        .fetch_optional(&state.db)
-                        .await
-                        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
-
-                if existing_username.is_none() {
-                    break;
+                attempts += 1;
+                if attempts > MAX_USERNAME_ATTEMPTS {
+                    return Err(http_error!(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        err: "Ran out of username attempts"
+                    ));
                 }
 
-                generated_username = memelibre_server::generate_username()?;
+                let user: Option<models::User> = sqlx::query_as(
+                    "
+                    INSERT INTO users (id, is_admin, username) VALUES ($1, false, $2)
+                    ON CONFLICT (username) DO NOTHING
+                    RETURNING id, is_admin, username
+                    ",
+                )
+                .bind(generate_user_id())
+                .bind(memelibre_server::generate_username()?)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+                if let Some(user) = user {
+                    break user;
+                }
             }
-
-            sqlx::query_as(
-                "INSERT INTO users (id, is_admin, username) VALUES ($1, false, $2) RETURNING id, is_admin, username",
-            )
-            .bind(&user_info.id)
-            .bind(&generated_username)
-            .fetch_one(&state.db)
-            .await
-            .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?
         }
     };
 
-    let claims = models::JWTClaims {
-        exp: (chrono::Utc::now() + chrono::Duration::days(1)).timestamp() as usize,
-        is_admin: user.is_admin,
-        sub: user.id,
-        username: user.username,
-    };
+    sqlx::query("INSERT INTO identities (provider, subject, user_id) VALUES ($1, $2, $3)")
+        .bind(provider)
+        .bind(subject)
+        .bind(&user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
 
-    let session_token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(&state.config.jwt_secret.as_bytes()),
-    )
-    .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+    tx.commit()
+        .await
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
 
-    Ok(session_token)
+    Ok(user)
 }
 
 pub async fn handler(
     State(state): State<Arc<models::AppState>>,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    headers: HeaderMap,
     jar: CookieJar,
     Query(params): Query<OAuthCallback>,
 ) -> Result<(CookieJar, Redirect), (StatusCode, String)> {
-    let client = reqwest::Client::new();
+    let session_client = sessions::Client::from_request(
+        &headers,
+        connect_info.map(|Extension(ConnectInfo(addr))| addr.ip()),
+        &state.config.trusted_proxies,
+    );
 
     // Step 1: Verify state parameter (CSRF protection)
     let stored_state = jar
         .get("oauth_state")
-        .ok_or(http_error!(StatusCode::UNAUTHORIZED))?
+        .ok_or_else(|| http_error!(StatusCode::UNAUTHORIZED))?
         .value();
 
     let received_state = params
         .state
         .as_ref()
-        .ok_or(http_error!(StatusCode::UNAUTHORIZED))?;
+        .ok_or_else(|| http_error!(StatusCode::UNAUTHORIZED))?;
 
     if stored_state != received_state {
         return Err(http_error!(StatusCode::UNAUTHORIZED));
     }
 
+    // Step 1b: Find the provider the login was started with
+    let provider = jar
+        .get("oauth_provider")
+        .and_then(|cookie| state.oauth.get(cookie.value()))
+        .ok_or_else(|| http_error!(StatusCode::UNAUTHORIZED))?;
+
     // Step 2: Check for authorization errors
     if let Some(e) = params.error {
         return Err(http_error!(StatusCode::UNAUTHORIZED, err: e));
     }
 
     // Step 2c: Extract authorization code
-    let auth_code = params.code.ok_or(http_error!(StatusCode::UNAUTHORIZED))?;
-
-    // Step 3: Exchange authorization code for access token
-    let token_response = exchange_code_for_token(&state, &client, &auth_code).await?;
+    let auth_code = params
+        .code
+        .ok_or_else(|| http_error!(StatusCode::UNAUTHORIZED))?;
+
+    // Step 3: Exchange authorization code for tokens, proving this browser started the login
+    let code_verifier = jar
+        .get("oauth_verifier")
+        .ok_or_else(|| http_error!(StatusCode::UNAUTHORIZED))?
+        .value();
 
-    // Step 4: Use access token to get user info
-    let user_info = get_user_info(&client, &token_response.access_token).await?;
+    let token_response = provider
+        .exchange_code(&state.config.oauth_redirect_uri, &auth_code, code_verifier)
+        .await
+        .map_err(|e| match e {
+            ExchangeError::Rejected(e) => http_error!(
+                StatusCode::UNAUTHORIZED,
+                format!("Authorization code rejected: {}", e)
+            ),
+            ExchangeError::Failed(e) => http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e),
+        })?;
+
+    // Step 4: Get the provider's id for the user, from the verified ID token when there is one
+    let subject = if provider.is_oidc() {
+        let id_token = token_response
+            .id_token
+            .as_ref()
+            .ok_or_else(|| http_error!(StatusCode::UNAUTHORIZED, "Missing ID token"))?;
+
+        let nonce = jar
+            .get("oauth_nonce")
+            .ok_or_else(|| http_error!(StatusCode::UNAUTHORIZED))?
+            .value();
+
+        provider
+            .verify_id_token(id_token, nonce)
+            .await
+            .map_err(|e| {
+                http_error!(StatusCode::UNAUTHORIZED, format!("Invalid ID token: {}", e))
+            })?
+    } else {
+        provider
+            .subject(&token_response.access_token)
+            .await
+            .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?
+    };
 
-    // Step 5: Create user session/JWT (implementation depends on your auth system)
-    let session_token = create_user_session(&state, &user_info).await?;
+    // Step 5: Find the user behind the identity, linking it when someone is signed in already
+    let signed_in_user_id = signed_in_user_id(&state, &jar).await?;
+    let user = find_or_create_user(&state, provider.name(), &subject, signed_in_user_id).await?;
 
-    // Step 6: Set session cookie and redirect
-    let session_cookie = Cookie::build(("session_token", session_token))
-        .http_only(true)
-        .max_age(cookie::time::Duration::days(15))
-        .path("/")
-        .same_site(SameSite::Lax)
-        .secure(true)
-        .build();
+    // Step 6: Start a session, the refresh token outlives the short access token
+    let tokens = sessions::create(&state.db, &state.config, &user, &session_client)
+        .await
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
 
-    let updated_jar = jar.add(session_cookie).remove(Cookie::from("oauth_state"));
+    // Step 7: Set session cookies and redirect
+    let updated_jar = cookies::add_session(jar, &state.config, tokens)
+        .remove(Cookie::from("oauth_state"))
+        .remove(Cookie::from("oauth_provider"))
+        .remove(Cookie::from("oauth_verifier"))
+        .remove(Cookie::from("oauth_nonce"));
 
     Ok((updated_jar, Redirect::to(&state.config.client_url)))
 }
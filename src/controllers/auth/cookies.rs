@@ -0,0 +1,50 @@
+use crate::models;
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use memelibre_server::sessions::Tokens;
+
+/// The refresh token is only ever needed by the auth endpoints, so no other request carries it
+const REFRESH_TOKEN_PATH: &str = "/api/auth";
+
+pub fn add_session(jar: CookieJar, config: &models::Config, tokens: Tokens) -> CookieJar {
+    let session_cookie = Cookie::build(("session_token", tokens.access_token))
+        .http_only(true)
+        .max_age(cookie::time::Duration::seconds(
+            config.session_access_ttl as i64,
+        ))
+        .path("/")
+        .same_site(SameSite::Lax)
+        .secure(true)
+        .build();
+
+    let refresh_cookie = Cookie::build(("refresh_token", tokens.refresh_token))
+        .http_only(true)
+        .max_age(cookie::time::Duration::seconds(
+            config.session_refresh_ttl as i64,
+        ))
+        .path(REFRESH_TOKEN_PATH)
+        .same_site(SameSite::Lax)
+        .secure(true)
+        .build();
+
+    jar.add(session_cookie).add(refresh_cookie)
+}
+
+pub fn remove_session(jar: CookieJar) -> CookieJar {
+    let session_cookie = Cookie::build(("session_token", ""))
+        .http_only(true)
+        .max_age(cookie::time::Duration::seconds(0))
+        .path("/")
+        .same_site(SameSite::Lax)
+        .secure(true)
+        .build();
+
+    let refresh_cookie = Cookie::build(("refresh_token", ""))
+        .http_only(true)
+        .max_age(cookie::time::Duration::seconds(0))
+        .path(REFRESH_TOKEN_PATH)
+        .same_site(SameSite::Lax)
+        .secure(true)
+        .build();
+
+    jar.add(session_cookie).add(refresh_cookie)
+}
@@ -1,43 +1,66 @@
+use crate::http_error;
 use crate::models;
-use axum::{extract::State, response::Redirect};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Redirect,
+};
 use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
 use rand::distr::Alphanumeric;
 use rand::{rng, Rng};
 use std::sync::Arc;
 
-fn generate_state() -> String {
+fn generate_secret(len: usize) -> String {
     rng()
         .sample_iter(Alphanumeric)
-        .take(32)
+        .take(len)
         .map(char::from)
         .collect()
 }
 
-pub async fn handler(
-    State(state): State<Arc<models::AppState>>,
-    jar: CookieJar,
-) -> (CookieJar, Redirect) {
-    let oauth_state = generate_state();
-    let redirect_uri = &state.config.oauth_redirect_uri;
-    let scope = "openid";
-
-    let cookie = Cookie::build(("oauth_state", oauth_state.clone()))
+fn oauth_cookie(name: &'static str, value: String) -> Cookie<'static> {
+    Cookie::build((name, value))
         .http_only(true)
         .max_age(cookie::time::Duration::seconds(15))
         .path("/")
         .same_site(SameSite::Lax)
         .secure(true)
-        .build();
+        .build()
+}
+
+/// `/login` goes to the first configured provider, `/login/{provider}` to any of them
+pub async fn handler(
+    State(state): State<Arc<models::AppState>>,
+    provider: Option<Path<String>>,
+    jar: CookieJar,
+) -> Result<(CookieJar, Redirect), (StatusCode, String)> {
+    let provider = match provider {
+        Some(Path(name)) => state.oauth.get(&name),
+        None => state.oauth.default_provider(),
+    }
+    .ok_or_else(|| http_error!(StatusCode::NOT_FOUND))?;
+
+    let oauth_state = generate_secret(32);
+    // PKCE binds the code to this browser, the nonce binds the ID token to this login
+    let code_verifier = generate_secret(64);
+    let nonce = generate_secret(32);
 
-    let updated_jar = jar.add(cookie);
+    let auth_url = provider
+        .authorization_url(
+            &state.config.oauth_redirect_uri,
+            &oauth_state,
+            &code_verifier,
+            &nonce,
+        )
+        .await
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
 
-    let auth_url = format!(
-        "https://accounts.google.com/o/oauth2/v2/auth?client_id={}&redirect_uri={}&response_type=code&scope={}&access_type=offline&prompt=consent&state={}",
-        &state.config.oauth_google_client_id,
-        redirect_uri,
-        scope,
-        oauth_state
-    );
+    // Every provider shares the callback, it tells which one answered by this cookie
+    let updated_jar = jar
+        .add(oauth_cookie("oauth_state", oauth_state))
+        .add(oauth_cookie("oauth_provider", provider.name().to_string()))
+        .add(oauth_cookie("oauth_verifier", code_verifier))
+        .add(oauth_cookie("oauth_nonce", nonce));
 
-    (updated_jar, Redirect::temporary(&auth_url))
+    Ok((updated_jar, Redirect::temporary(auth_url.as_str())))
 }
@@ -1,21 +1,46 @@
+use crate::controllers::auth::cookies;
+use crate::http_error;
 use crate::models;
-use axum::{extract::State, response::Redirect};
-use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use axum::{extract::State, http::StatusCode, response::Redirect};
+use axum_extra::extract::cookie::CookieJar;
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use memelibre_server::sessions;
 use std::sync::Arc;
 
+/// An expired access token still names the session, its signature proves it was issued for it
+fn access_session_id(jar: &CookieJar, jwt_secret: &str) -> Option<String> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.validate_exp = false;
+
+    decode::<models::JWTClaims>(
+        jar.get("session_token")?.value(),
+        &DecodingKey::from_secret(jwt_secret.as_bytes()),
+        &validation,
+    )
+    .ok()
+    .map(|token| token.claims.sid)
+}
+
 pub async fn handler(
     State(state): State<Arc<models::AppState>>,
     jar: CookieJar,
-) -> (CookieJar, Redirect) {
-    let session_cookie = Cookie::build(("session_token", ""))
-        .http_only(true)
-        .max_age(cookie::time::Duration::seconds(0))
-        .path("/")
-        .same_site(SameSite::Lax)
-        .secure(true)
-        .build();
+) -> Result<(CookieJar, Redirect), (StatusCode, String)> {
+    // Either cookie has to prove it belongs to the session, a session id alone isn't enough
+    let revoked = match jar.get("refresh_token") {
+        Some(cookie) => sessions::revoke_with_refresh_token(&state.db, cookie.value())
+            .await
+            .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?,
+        None => false,
+    };
 
-    let updated_jar = jar.add(session_cookie);
+    if let Some(id) = access_session_id(&jar, &state.config.jwt_secret).filter(|_| !revoked) {
+        sessions::revoke(&state.db, &id)
+            .await
+            .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+    }
 
-    (updated_jar, Redirect::permanent(&state.config.client_url))
+    Ok((
+        cookies::remove_session(jar),
+        Redirect::to(&state.config.client_url),
+    ))
 }
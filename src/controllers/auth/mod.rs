@@ -1,4 +1,6 @@
 pub mod callback;
+pub mod cookies;
 pub mod login;
 pub mod logout;
 pub mod me;
+pub mod refresh;
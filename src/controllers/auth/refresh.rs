@@ -0,0 +1,32 @@
+use crate::controllers::auth::cookies;
+use crate::http_error;
+use crate::models;
+use axum::{extract::State, http::StatusCode};
+use axum_extra::extract::cookie::CookieJar;
+use memelibre_server::sessions::{self, Refresh};
+use std::sync::Arc;
+
+pub async fn handler(
+    State(state): State<Arc<models::AppState>>,
+    jar: CookieJar,
+) -> Result<(CookieJar, StatusCode), (StatusCode, String)> {
+    let refresh_token = jar
+        .get("refresh_token")
+        .ok_or_else(|| http_error!(StatusCode::UNAUTHORIZED))?
+        .value()
+        .to_string();
+
+    let refresh = sessions::refresh(&state.db, &state.config, &refresh_token)
+        .await
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    match refresh {
+        Refresh::Rotated(tokens) => Ok((
+            cookies::add_session(jar, &state.config, tokens),
+            StatusCode::NO_CONTENT,
+        )),
+        // The cookies are left alone, the request that won already replaced them
+        Refresh::Superseded => Ok((jar, StatusCode::CONFLICT)),
+        Refresh::Rejected => Ok((cookies::remove_session(jar), StatusCode::UNAUTHORIZED)),
+    }
+}
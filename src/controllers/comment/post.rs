@@ -20,7 +20,7 @@ pub async fn handler(
     Json(payload): Json<PostCommentReq>,
 ) -> Result<StatusCode, (StatusCode, String)> {
     sqlx::query("INSERT INTO comments (meme_id, user_id, content) VALUES ($1, $2, $3)")
-        .bind(&meme_id)
+        .bind(meme_id)
         .bind(&claims.sub)
         .bind(&payload.content)
         .execute(&state.db)
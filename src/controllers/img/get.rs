@@ -0,0 +1,142 @@
+use crate::http_error;
+use crate::models;
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+};
+use bytes::Bytes;
+use memelibre_server::{
+    processing::{
+        resize::{self, Format},
+        RENDITIONS,
+    },
+    storage,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+pub struct ImgReq {
+    format: Option<String>,
+    quality: Option<u8>,
+    width: Option<u32>,
+}
+
+pub async fn handler(
+    State(state): State<Arc<models::AppState>>,
+    Path(id): Path<i32>,
+    Query(query): Query<ImgReq>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let format = match &query.format {
+        Some(format) => format
+            .parse()
+            .map_err(|e: String| http_error!(StatusCode::BAD_REQUEST, e))?,
+        None => Format::negotiate(
+            headers
+                .get(header::ACCEPT)
+                .and_then(|accept| accept.to_str().ok())
+                .unwrap_or_default(),
+        ),
+    };
+
+    // Widths are rounded up to a rendition width, past the widest one the image keeps its size,
+    // and qualities to a fixed level. Every variant gets encoded and stored once, so clients
+    // can't have as many made as they like
+    let quality = format.quality(query.quality, &state.config);
+    let width = query.width.and_then(|width| {
+        RENDITIONS
+            .iter()
+            .filter_map(|(_, max_width)| *max_width)
+            .filter(|max_width| *max_width >= width)
+            .min()
+    });
+
+    // Videos are resized from their poster
+    let meme: Option<(Option<String>, String, Option<bool>, String)> = sqlx::query_as(
+        "
+        SELECT
+            CASE WHEN memes.media_type = 'video' THEN poster.object_key ELSE memes.object_key END,
+            memes.image_url,
+            memes.is_animated,
+            memes.media_type
+        FROM memes
+        LEFT JOIN meme_renditions poster
+            ON poster.meme_id = memes.id
+            AND poster.name = 'poster'
+            AND poster.mime_type = 'image/webp'
+        WHERE memes.id = $1
+        ",
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    let (source_key, image_url, is_animated, media_type) =
+        meme.ok_or(http_error!(StatusCode::NOT_FOUND))?;
+
+    // Resizing keeps only the first frame, animations are better served as they are
+    if media_type == "image" && is_animated == Some(true) {
+        return Ok(Redirect::temporary(&image_url).into_response());
+    }
+
+    let source_key = source_key.ok_or(http_error!(StatusCode::NOT_FOUND))?;
+
+    let variant = format!(
+        "{}-q{}.{}",
+        width.map_or("full".to_string(), |width| format!("{}w", width)),
+        quality,
+        format.extension()
+    );
+    let cache_key = storage::variant_key(&source_key, &variant);
+
+    if let Some(data) = state.image_cache.get(&cache_key) {
+        return Ok(image_response(format, data));
+    }
+
+    if let Ok(data) = state.storage.get(&cache_key).await {
+        let data = Bytes::from(data);
+        state.image_cache.insert(cache_key, data.clone());
+        return Ok(image_response(format, data));
+    }
+
+    let original = state
+        .storage
+        .get(&source_key)
+        .await
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    let processing_state = state.clone();
+    let data = state
+        .resizing
+        .run(move || resize::resize(&original, width, format, quality, &processing_state.config))
+        .await?;
+
+    if let Err(e) = state
+        .storage
+        .put(&cache_key, data.clone(), format.content_type())
+        .await
+    {
+        eprintln!("Failed to cache image variant {}: {}", cache_key, e);
+    }
+
+    let data = Bytes::from(data);
+    state.image_cache.insert(cache_key, data.clone());
+
+    Ok(image_response(format, data))
+}
+
+/// Variants of a meme never change, only which one is picked depends on `Accept`
+fn image_response(format: Format, data: Bytes) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type()),
+            (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+            (header::VARY, "Accept"),
+        ],
+        data,
+    )
+        .into_response()
+}
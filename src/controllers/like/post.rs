@@ -19,7 +19,7 @@ pub async fn handler(
 
     let existing_like: Option<models::Like> =
         sqlx::query_as("SELECT 1 FROM likes WHERE meme_id = $1 AND user_id = $2")
-            .bind(&meme_id)
+            .bind(meme_id)
             .bind(&claims.sub)
             .fetch_optional(&mut *tx)
             .await
@@ -27,14 +27,14 @@ pub async fn handler(
 
     if existing_like.is_some() {
         sqlx::query("DELETE FROM likes WHERE meme_id = $1 AND user_id = $2")
-            .bind(&meme_id)
+            .bind(meme_id)
             .bind(&claims.sub)
             .execute(&mut *tx)
             .await
             .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
 
         sqlx::query("UPDATE memes SET like_count = like_count - 1 WHERE id = $1")
-            .bind(&meme_id)
+            .bind(meme_id)
             .execute(&mut *tx)
             .await
             .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
@@ -46,14 +46,14 @@ pub async fn handler(
         Ok(StatusCode::NO_CONTENT)
     } else {
         sqlx::query("INSERT INTO likes (meme_id, user_id) VALUES ($1, $2)")
-            .bind(&meme_id)
+            .bind(meme_id)
             .bind(&claims.sub)
             .execute(&mut *tx)
             .await
             .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
 
         sqlx::query("UPDATE memes SET like_count = like_count + 1 WHERE id = $1")
-            .bind(&meme_id)
+            .bind(meme_id)
             .execute(&mut *tx)
             .await
             .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
@@ -4,43 +4,55 @@ use axum::{
     extract::{Path, State},
     http::StatusCode,
 };
-use memelibre_server::create_bucket_client;
 use std::sync::Arc;
 
 pub async fn handler(
     State(state): State<Arc<models::AppState>>,
     Path(id): Path<i32>,
 ) -> Result<StatusCode, (StatusCode, String)> {
-    let meme: models::Meme = sqlx::query_as("SELECT id, image_url FROM memes WHERE id = $1")
-        .bind(id)
-        .fetch_optional(&state.db)
+    let mut tx = state
+        .db
+        .begin()
         .await
-        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?
-        .ok_or(http_error!(StatusCode::NOT_FOUND))?;
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    let object_keys: Vec<(String,)> = sqlx::query_as(
+        "
+        SELECT object_key FROM memes WHERE id = $1
+        UNION
+        SELECT object_key FROM meme_renditions WHERE meme_id = $1
+        ",
+    )
+    .bind(id)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    if object_keys.is_empty() {
+        return Err(http_error!(StatusCode::NOT_FOUND));
+    }
 
     sqlx::query("DELETE FROM memes WHERE id = $1")
         .bind(id)
-        .execute(&state.db)
+        .execute(&mut *tx)
         .await
         .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
 
-    let bucket_client = create_bucket_client()
-        .await
-        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+    // Objects are deleted by the deletions worker, queued in the same transaction so a failed
+    // bucket delete is retried instead of leaving an object nobody references
+    for (object_key,) in &object_keys {
+        sqlx::query("INSERT INTO pending_deletions (object_key) VALUES ($1)")
+            .bind(object_key)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+    }
 
-    let object_key = meme
-        .image_url
-        .rsplit('/')
-        .next()
-        .ok_or(http_error!(StatusCode::NOT_FOUND))?;
-
-    bucket_client
-        .delete_object()
-        .bucket(&state.config.bucket_name)
-        .key(object_key)
-        .send()
+    tx.commit()
         .await
         .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
 
+    state.deletions.notify_one();
+
     Ok(StatusCode::NO_CONTENT)
 }
@@ -0,0 +1,22 @@
+use crate::http_error;
+use crate::models;
+use axum::{extract::State, http::StatusCode, response::Json};
+use std::sync::Arc;
+
+pub async fn handler(
+    State(state): State<Arc<models::AppState>>,
+) -> Result<Json<Vec<models::PendingDeletion>>, (StatusCode, String)> {
+    let pending: Vec<models::PendingDeletion> = sqlx::query_as(
+        "
+        SELECT attempts, created_at, id, last_error, next_attempt_at, object_key
+        FROM pending_deletions
+        WHERE completed_at IS NULL
+        ORDER BY id ASC
+        ",
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    Ok(Json(pending))
+}
@@ -0,0 +1,92 @@
+use crate::controllers::meme::post::create_meme;
+use crate::http_error;
+use crate::models;
+use axum::{
+    extract::{Extension, State},
+    http::StatusCode,
+    response::{self, Json},
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+pub struct FinalizeReq {
+    key: String,
+    poster_key: Option<String>,
+    watermark: Option<bool>,
+}
+
+pub async fn handler(
+    State(state): State<Arc<models::AppState>>,
+    Extension(claims): Extension<models::JWTClaims>,
+    Json(payload): Json<FinalizeReq>,
+) -> response::Result<(StatusCode, String)> {
+    // Users can only finalize what they staged through a URL issued by meme::presign
+    let prefix = format!("staging/{}/", claims.sub);
+    let staged_keys: Vec<&String> = std::iter::once(&payload.key)
+        .chain(payload.poster_key.as_ref())
+        .collect();
+
+    if staged_keys
+        .iter()
+        .any(|key| !key.starts_with(&prefix) || key.contains(".."))
+    {
+        return Err(http_error!(StatusCode::FORBIDDEN, "Not a staged upload of yours").into());
+    }
+
+    let result = finalize(&state, &claims, &payload).await;
+
+    // Staged uploads are never needed again, whether they made it into a meme or not
+    for key in staged_keys {
+        if let Err(e) = state.storage.delete(key).await {
+            eprintln!("Failed to delete staged upload {}: {}", key, e);
+        }
+    }
+
+    result
+}
+
+async fn finalize(
+    state: &Arc<models::AppState>,
+    claims: &models::JWTClaims,
+    payload: &FinalizeReq,
+) -> response::Result<(StatusCode, String)> {
+    let file_data = get_staged(state, &payload.key, state.config.max_upload_size()).await?;
+
+    let poster = match &payload.poster_key {
+        Some(poster_key) => {
+            Some(get_staged(state, poster_key, state.config.bucket_object_max_size).await?)
+        }
+        None => None,
+    };
+
+    let watermark = match payload.watermark {
+        Some(watermark) if claims.is_admin => watermark,
+        _ => state.config.watermark_enabled,
+    };
+
+    create_meme(state, claims, file_data, poster, watermark).await
+}
+
+async fn get_staged(
+    state: &models::AppState,
+    key: &str,
+    max_size: usize,
+) -> Result<bytes::Bytes, (StatusCode, String)> {
+    let size = state
+        .storage
+        .size(key)
+        .await
+        .map_err(|_| http_error!(StatusCode::NOT_FOUND))?;
+
+    if size > max_size as u64 {
+        return Err(http_error!(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    state
+        .storage
+        .get(key)
+        .await
+        .map(bytes::Bytes::from)
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))
+}
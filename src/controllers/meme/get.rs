@@ -11,28 +11,57 @@ pub async fn handler(
     State(state): State<Arc<models::AppState>>,
     Query(params): Query<models::Pagination>,
 ) -> Result<Json<Vec<models::MemeWithUsernameAndCommentsCount>>, (StatusCode, String)> {
-    let memes: Vec<models::MemeWithUsernameAndCommentsCount> = sqlx::query_as(
+    let mut memes: Vec<models::MemeWithUsernameAndCommentsCount> = sqlx::query_as(
         "
         SELECT
             COALESCE(COUNT(comments.id), 0) as comment_count,
+            memes.blurhash,
+            memes.byte_size,
+            memes.height,
             memes.id,
             memes.image_url,
+            memes.is_animated,
             memes.like_count,
+            memes.media_type,
+            memes.mime_type,
+            memes.poster_url,
+            memes.width,
             users.username
         FROM memes
         LEFT JOIN users ON memes.created_by = users.id
         LEFT JOIN comments ON memes.id = comments.meme_id
         WHERE memes.id < COALESCE($1, 2147483647)
-        GROUP BY memes.id, memes.image_url, memes.like_count, users.username
+        GROUP BY memes.id, users.username
         ORDER BY memes.id DESC
         LIMIT $2;
         ",
     )
     .bind(params.offset)
-    .bind(&state.config.memes_pull_limit)
+    .bind(state.config.memes_pull_limit)
     .fetch_all(&state.db)
     .await
     .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
 
+    let meme_ids: Vec<i32> = memes.iter().map(|meme| meme.id).collect();
+
+    let renditions: Vec<models::MemeRendition> = sqlx::query_as(
+        "
+        SELECT height, image_url, meme_id, mime_type, name, width
+        FROM meme_renditions
+        WHERE meme_id = ANY($1)
+        ORDER BY width ASC
+        ",
+    )
+    .bind(&meme_ids)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    for rendition in renditions {
+        if let Some(meme) = memes.iter_mut().find(|meme| meme.id == rendition.meme_id) {
+            meme.renditions.push(rendition);
+        }
+    }
+
     Ok(Json(memes))
 }
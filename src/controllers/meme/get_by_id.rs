@@ -14,9 +14,17 @@ pub async fn handler(
     let meme: Option<models::MemeWithUsername> = sqlx::query_as(
         "
             SELECT
+                memes.blurhash,
+                memes.byte_size,
+                memes.height,
                 memes.id,
                 memes.image_url,
+                memes.is_animated,
                 memes.like_count,
+                memes.media_type,
+                memes.mime_type,
+                memes.poster_url,
+                memes.width,
                 users.username
             FROM memes
             LEFT JOIN users ON memes.created_by = users.id
@@ -52,6 +60,7 @@ pub async fn handler(
         id: meme.id,
         image_url: meme.image_url,
         like_count: meme.like_count,
+        media: meme.media,
         username: meme.username,
         comments,
     };
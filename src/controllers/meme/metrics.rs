@@ -0,0 +1,8 @@
+use crate::models;
+use axum::{extract::State, response::Json};
+use memelibre_server::processing::pool::PoolMetrics;
+use std::sync::Arc;
+
+pub async fn handler(State(state): State<Arc<models::AppState>>) -> Json<PoolMetrics> {
+    Json(state.processing.metrics())
+}
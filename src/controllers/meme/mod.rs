@@ -1,4 +1,8 @@
 pub mod delete;
+pub mod deletions;
+pub mod finalize;
 pub mod get;
 pub mod get_by_id;
+pub mod metrics;
 pub mod post;
+pub mod presign;
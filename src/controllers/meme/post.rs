@@ -1,110 +1,370 @@
 use crate::http_error;
 use crate::models;
-use aws_sdk_s3::primitives::ByteStream;
 use axum::{
-    extract::{Extension, Multipart, State},
+    extract::{multipart::Field, Extension, Multipart, State},
     http::status::StatusCode,
+    response::{self, ErrorResponse, Json},
 };
-use chrono::Utc;
-use image::{ImageFormat, ImageReader};
-use memelibre_server::create_bucket_client;
-use std::io::Cursor;
+use memelibre_server::{deletions, processing, processing::phash, processing::video, storage};
+use serde::Serialize;
 use std::sync::Arc;
-use webp::Encoder;
+
+/// Advisory lock held while checking for duplicates and inserting a meme
+const DUPLICATES_LOCK: i64 = 0x6d656d65;
+const WATERMARK_FIELD_MAX_SIZE: usize = 16;
+
+#[derive(Serialize)]
+struct DuplicateRes {
+    duplicate_of: i32,
+    error: &'static str,
+}
+
+struct StoredRendition {
+    byte_size: i64,
+    height: i32,
+    image_url: String,
+    mime_type: &'static str,
+    name: &'static str,
+    object_key: String,
+    width: i32,
+}
 
 pub async fn handler(
     State(state): State<Arc<models::AppState>>,
     Extension(claims): Extension<models::JWTClaims>,
     mut multipart: Multipart,
-) -> Result<(StatusCode, String), (StatusCode, String)> {
+) -> response::Result<(StatusCode, String)> {
     let mut file_data: Option<bytes::Bytes> = None;
+    let mut poster: Option<bytes::Bytes> = None;
+    let mut watermark: Option<bool> = None;
 
     while let Some(field) = multipart
         .next_field()
         .await
-        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?
+        .map_err(|e| http_error!(e.status(), e.body_text()))?
     {
-        if field.name() == Some("file") {
-            let data = field
-                .bytes()
-                .await
-                .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+        let name = field.name().unwrap_or_default().to_string();
 
-            if data.len() > state.config.bucket_object_max_size {
-                return Err(http_error!(StatusCode::PAYLOAD_TOO_LARGE));
+        let duplicate = match name.as_str() {
+            "file" => file_data
+                .replace(read_field(field, state.config.max_upload_size()).await?)
+                .is_some(),
+            "poster" => poster
+                .replace(read_field(field, state.config.bucket_object_max_size).await?)
+                .is_some(),
+            "watermark" => {
+                let value = read_field(field, WATERMARK_FIELD_MAX_SIZE).await?;
+                let value = std::str::from_utf8(&value)
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+                    .ok_or_else(|| {
+                        http_error!(StatusCode::BAD_REQUEST, "Invalid watermark value")
+                    })?;
+                watermark.replace(value).is_some()
+            }
+            _ => {
+                return Err(
+                    http_error!(StatusCode::BAD_REQUEST, format!("Unknown field {}", name)).into(),
+                )
             }
-            file_data = Some(data);
+        };
+
+        if duplicate {
+            return Err(
+                http_error!(StatusCode::BAD_REQUEST, format!("Duplicate field {}", name)).into(),
+            );
         }
     }
 
     let file_data = file_data.ok_or((StatusCode::BAD_REQUEST, "File is empty".to_string()))?;
+    let watermark = match watermark {
+        Some(watermark) if claims.is_admin => watermark,
+        _ => state.config.watermark_enabled,
+    };
+
+    create_meme(&state, &claims, file_data, poster, watermark).await
+}
+
+/// Reads a field chunk by chunk, giving up as soon as it grows past `max_size` instead of
+/// buffering whatever the client keeps sending
+async fn read_field(
+    mut field: Field<'_>,
+    max_size: usize,
+) -> Result<bytes::Bytes, (StatusCode, String)> {
+    let mut data = bytes::BytesMut::new();
+
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|e| http_error!(e.status(), e.body_text()))?
+    {
+        if data.len() + chunk.len() > max_size {
+            return Err(http_error!(StatusCode::PAYLOAD_TOO_LARGE));
+        }
+        data.extend_from_slice(&chunk);
+    }
 
-    let guessed_format = ImageReader::new(Cursor::new(&file_data))
-        .with_guessed_format()
-        .map_err(|_| http_error!(StatusCode::BAD_REQUEST, "Invalid image format"))?
-        .format();
+    Ok(data.freeze())
+}
 
-    let (data, extension, content_type) = if guessed_format == Some(ImageFormat::Gif) {
-        (file_data.to_vec(), "gif", "image/gif")
-    } else {
-        let img = ImageReader::new(Cursor::new(&file_data))
-            .with_guessed_format()
-            .map_err(|_| http_error!(StatusCode::BAD_REQUEST, "Invalid image format"))?
-            .decode()
-            .map_err(|_| http_error!(StatusCode::BAD_REQUEST, "Invalid image format"))?;
+/// Processes, stores and records an uploaded image or video, shared by multipart and presigned
+/// uploads. `poster` is only used for videos whose first frame can't be decoded
+pub async fn create_meme(
+    state: &Arc<models::AppState>,
+    claims: &models::JWTClaims,
+    file_data: bytes::Bytes,
+    poster: Option<bytes::Bytes>,
+    watermark: bool,
+) -> response::Result<(StatusCode, String)> {
+    let processing_state = state.clone();
+    let processed = state
+        .processing
+        .run(move || {
+            let config = &processing_state.config;
+            let watermark = watermark.then_some(&processing_state.watermark);
 
-        let rgba = img.to_rgba8();
-        let (width, height) = rgba.dimensions();
+            match video::sniff(&file_data) {
+                Some(container) => {
+                    video::process(&file_data, container, poster.as_deref(), config, watermark)
+                }
+                None => processing::process(&file_data, config, watermark),
+            }
+        })
+        .await?;
 
-        let webp_data = Encoder::from_rgba(&rgba, width, height)
-            .encode(state.config.compression_quality)
-            .to_vec();
+    // Postgres has no unsigned integers, the hash bits are stored as they are in a BIGINT
+    let phash = processed.phash.map(|phash| phash as i64);
 
-        (webp_data, "webp", "image/webp")
+    let duplicate_phash = match state.config.duplicate_action {
+        models::DuplicateAction::Off => None,
+        _ => phash,
     };
+    let reject_duplicates = state.config.duplicate_action == models::DuplicateAction::Reject;
 
-    let timestamp = Utc::now().format("%Y-%m-%d_%H:%M:%S%.3f").to_string();
-    let unique_filename = format!("{}.{}", timestamp, extension);
-
-    let image_url = format!(
-        "https://{}.{}/{}",
-        &state.config.bucket_name,
-        &state
-            .config
-            .bucket_endpoint
-            .strip_prefix("https://")
-            .ok_or_else(|| http_error!(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "BUCKET_ENDPOINT env var missing https:// prefix"
-            ))?,
-        unique_filename
-    );
+    // Turns most reposts down before anything gets stored, the check that counts is the one
+    // made again along with the insert
+    if let Some(phash) = duplicate_phash.filter(|_| reject_duplicates) {
+        let duplicate_of =
+            find_duplicate(&state.db, phash, state.config.duplicate_max_distance).await?;
 
-    let bucket_client = create_bucket_client()
+        if let Some(original_id) = duplicate_of {
+            return Err(duplicate_error(original_id));
+        }
+    }
+
+    let renditions: Vec<(String, processing::Rendition)> = processed
+        .renditions
+        .into_iter()
+        .chain(processed.poster)
+        .map(|rendition| {
+            let object_key = storage::content_key(&rendition.data, rendition.extension);
+            (object_key, rendition)
+        })
+        .collect();
+
+    let mut tx = state
+        .db
+        .begin()
         .await
         .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
 
-    let put_result = bucket_client
-        .put_object()
-        .bucket(&state.config.bucket_name)
-        .key(&unique_filename)
-        .body(ByteStream::from(data))
-        .content_type(content_type)
-        .acl("public-read".into())
-        .send()
-        .await;
-
-    match put_result {
-        Ok(_) => {
-            sqlx::query("INSERT INTO memes (created_by, image_url, like_count) VALUES ($1, $2, 0)")
-                .bind(&claims.sub)
-                .bind(&image_url)
-                .execute(&state.db)
+    // An identical file may be queued for deletion, its keys are held from before they are
+    // stored until the rows referencing them commit so the worker can't delete them in between.
+    // Sorted so two uploads sharing keys take them in the same order
+    let mut object_keys: Vec<&String> = renditions.iter().map(|(key, _)| key).collect();
+    object_keys.sort();
+    object_keys.dedup();
+
+    for object_key in object_keys {
+        deletions::lock_object_key(&mut tx, object_key)
+            .await
+            .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+    }
+
+    let mut stored_renditions = Vec::with_capacity(renditions.len());
+
+    for (object_key, rendition) in renditions {
+        let byte_size = rendition.data.len() as i64;
+
+        state
+            .storage
+            .put(&object_key, rendition.data, rendition.content_type)
+            .await
+            .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+        stored_renditions.push(StoredRendition {
+            byte_size,
+            height: rendition.height as i32,
+            image_url: state.storage.public_url(&object_key),
+            mime_type: rendition.content_type,
+            name: rendition.name,
+            object_key,
+            width: rendition.width as i32,
+        });
+    }
+
+    let full = stored_renditions
+        .iter()
+        .find(|rendition| rendition.name == "full" && rendition.mime_type == processed.content_type)
+        .ok_or_else(|| http_error!(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    let poster_url = stored_renditions
+        .iter()
+        .find(|rendition| rendition.name == "poster" && rendition.mime_type == "image/webp")
+        .map(|rendition| &rendition.image_url);
+
+    let duplicate_of = match duplicate_phash {
+        Some(phash) => {
+            // Two copies of a meme uploaded at once would otherwise both miss each other
+            sqlx::query("SELECT pg_advisory_xact_lock($1)")
+                .bind(DUPLICATES_LOCK)
+                .execute(&mut *tx)
                 .await
                 .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
 
-            Ok((StatusCode::CREATED, "Upload successful".to_string()))
+            find_duplicate(&mut *tx, phash, state.config.duplicate_max_distance).await?
         }
-        Err(err) => Err(http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: err)),
+        None => None,
+    };
+
+    if let Some(original_id) = duplicate_of.filter(|_| reject_duplicates) {
+        // The keys may be the original's own. The worker only gets them once this transaction
+        // lets go of them and keeps whatever a meme still references by then
+        for rendition in &stored_renditions {
+            sqlx::query("INSERT INTO pending_deletions (object_key) VALUES ($1)")
+                .bind(&rendition.object_key)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+        }
+
+        tx.commit()
+            .await
+            .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+        state.deletions.notify_one();
+
+        return Err(duplicate_error(original_id));
     }
+
+    let (meme_id,): (i32,) = sqlx::query_as(
+        "
+        INSERT INTO memes (
+            blurhash,
+            byte_size,
+            compression_quality,
+            created_by,
+            duplicate_of,
+            height,
+            image_url,
+            is_animated,
+            like_count,
+            media_type,
+            mime_type,
+            object_key,
+            phash,
+            poster_url,
+            width
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, 0, $9, $10, $11, $12, $13, $14)
+        RETURNING id
+        ",
+    )
+    .bind(&processed.blurhash)
+    .bind(full.byte_size)
+    .bind(processed.quality)
+    .bind(&claims.sub)
+    .bind(duplicate_of)
+    .bind(full.height)
+    .bind(&full.image_url)
+    .bind(processed.animated)
+    .bind(processed.media_type)
+    .bind(processed.content_type)
+    .bind(&full.object_key)
+    .bind(phash)
+    .bind(poster_url)
+    .bind(full.width)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    for rendition in &stored_renditions {
+        sqlx::query(
+            "
+            INSERT INTO meme_renditions (
+                meme_id,
+                name,
+                mime_type,
+                image_url,
+                object_key,
+                width,
+                height
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ",
+        )
+        .bind(meme_id)
+        .bind(rendition.name)
+        .bind(rendition.mime_type)
+        .bind(&rendition.image_url)
+        .bind(&rendition.object_key)
+        .bind(rendition.width)
+        .bind(rendition.height)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+    }
+
+    tx.commit()
+        .await
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    Ok((StatusCode::CREATED, "Upload successful".to_string()))
+}
+
+/// Closest meme at most `max_distance` bits away from `phash`, looked up through the bands
+/// index instead of comparing against every meme
+async fn find_duplicate<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    phash: i64,
+    max_distance: u32,
+) -> Result<Option<i32>, (StatusCode, String)> {
+    let duplicate: Option<(i32,)> = sqlx::query_as(
+        "
+        SELECT id
+        FROM memes
+        WHERE phash_bands && $2
+        AND bit_count((phash # $1)::bit(64)) <= $3
+        ORDER BY bit_count((phash # $1)::bit(64)) ASC, id ASC
+        LIMIT 1
+        ",
+    )
+    .bind(phash)
+    .bind(phash::bands(phash as u64))
+    .bind(max_distance as i64)
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    Ok(duplicate.map(|(id,)| id))
+}
+
+fn duplicate_error(original_id: i32) -> ErrorResponse {
+    let error = "Meme was already uploaded";
+    eprintln!(
+        "{}:{} - HTTP 409 {} as {}",
+        file!(),
+        line!(),
+        error,
+        original_id
+    );
+
+    (
+        StatusCode::CONFLICT,
+        Json(DuplicateRes {
+            duplicate_of: original_id,
+            error,
+        }),
+    )
+        .into()
 }
@@ -0,0 +1,62 @@
+use crate::http_error;
+use crate::models;
+use axum::{
+    extract::{Extension, State},
+    http::StatusCode,
+    response::Json,
+};
+use rand::distr::Alphanumeric;
+use rand::{rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+pub struct PresignReq {
+    size: u64,
+}
+
+#[derive(Serialize)]
+pub struct PresignRes {
+    expires_in: u64,
+    key: String,
+    upload_url: String,
+}
+
+pub async fn handler(
+    State(state): State<Arc<models::AppState>>,
+    Extension(claims): Extension<models::JWTClaims>,
+    Json(payload): Json<PresignReq>,
+) -> Result<Json<PresignRes>, (StatusCode, String)> {
+    if payload.size == 0 {
+        return Err(http_error!(StatusCode::BAD_REQUEST, "File is empty"));
+    }
+
+    if payload.size > state.config.max_upload_size() as u64 {
+        return Err(http_error!(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    let id: String = rng()
+        .sample_iter(Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+
+    let key = format!("staging/{}/{}", claims.sub, id);
+
+    let upload_url = state
+        .storage
+        .presign_put(
+            &key,
+            payload.size,
+            Duration::from_secs(state.config.presign_expiration),
+        )
+        .await
+        .map_err(|e| http_error!(StatusCode::NOT_IMPLEMENTED, e))?;
+
+    Ok(Json(PresignRes {
+        expires_in: state.config.presign_expiration,
+        key,
+        upload_url,
+    }))
+}
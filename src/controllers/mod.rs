@@ -1,6 +1,8 @@
 pub mod auth;
 pub mod comment;
+pub mod img;
 pub mod like;
 pub mod meme;
 pub mod save;
+pub mod session;
 pub mod user;
@@ -14,10 +14,18 @@ pub async fn handler(
     let saved: Vec<models::Meme> = sqlx::query_as(
         "
         SELECT
+            memes.blurhash,
+            memes.byte_size,
             memes.created_by,
+            memes.height,
             memes.id,
             memes.image_url,
-            memes.like_count
+            memes.is_animated,
+            memes.like_count,
+            memes.media_type,
+            memes.mime_type,
+            memes.poster_url,
+            memes.width
         FROM memes
         JOIN saved ON saved.meme_id = memes.id
         WHERE saved.user_id = $1
@@ -13,7 +13,7 @@ pub async fn handler(
 ) -> Result<StatusCode, (StatusCode, String)> {
     let existing_saved: Option<models::Save> =
         sqlx::query_as("SELECT 1 FROM saved WHERE meme_id = $1 AND user_id = $2")
-            .bind(&meme_id)
+            .bind(meme_id)
             .bind(&claims.sub)
             .fetch_optional(&state.db)
             .await
@@ -21,7 +21,7 @@ pub async fn handler(
 
     if existing_saved.is_some() {
         sqlx::query("DELETE FROM saved WHERE meme_id = $1 AND user_id = $2")
-            .bind(&meme_id)
+            .bind(meme_id)
             .bind(&claims.sub)
             .execute(&state.db)
             .await
@@ -30,7 +30,7 @@ pub async fn handler(
         Ok(StatusCode::NO_CONTENT)
     } else {
         sqlx::query("INSERT INTO saved (meme_id, user_id) VALUES ($1, $2)")
-            .bind(&meme_id)
+            .bind(meme_id)
             .bind(&claims.sub)
             .execute(&state.db)
             .await
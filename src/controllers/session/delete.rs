@@ -0,0 +1,33 @@
+use crate::http_error;
+use crate::models;
+use axum::{
+    extract::{Extension, Path, State},
+    http::StatusCode,
+};
+use memelibre_server::sessions;
+use std::sync::Arc;
+
+pub async fn handler(
+    State(state): State<Arc<models::AppState>>,
+    Extension(claims): Extension<models::JWTClaims>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let session: Option<(String,)> = sqlx::query_as(
+        "SELECT id FROM sessions WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+    )
+    .bind(&id)
+    .bind(&claims.sub)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    if session.is_none() {
+        return Err(http_error!(StatusCode::NOT_FOUND));
+    }
+
+    sessions::revoke(&state.db, &id)
+        .await
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
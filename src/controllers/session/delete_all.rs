@@ -0,0 +1,20 @@
+use crate::http_error;
+use crate::models;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use memelibre_server::sessions;
+use std::sync::Arc;
+
+/// Admin only, logs a user out everywhere
+pub async fn handler(
+    State(state): State<Arc<models::AppState>>,
+    Path(user_id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    sessions::revoke_all(&state.db, &user_id, None)
+        .await
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,19 @@
+use crate::http_error;
+use crate::models;
+use axum::{
+    extract::{Extension, State},
+    http::StatusCode,
+};
+use memelibre_server::sessions;
+use std::sync::Arc;
+
+pub async fn handler(
+    State(state): State<Arc<models::AppState>>,
+    Extension(claims): Extension<models::JWTClaims>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    sessions::revoke_all(&state.db, &claims.sub, Some(&claims.sid))
+        .await
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
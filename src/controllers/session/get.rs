@@ -0,0 +1,29 @@
+use crate::http_error;
+use crate::models;
+use axum::{
+    extract::{Extension, State},
+    http::StatusCode,
+    response::Json,
+};
+use std::sync::Arc;
+
+pub async fn handler(
+    State(state): State<Arc<models::AppState>>,
+    Extension(claims): Extension<models::JWTClaims>,
+) -> Result<Json<Vec<models::Session>>, (StatusCode, String)> {
+    let sessions: Vec<models::Session> = sqlx::query_as(
+        "
+        SELECT created_at, id = $2 AS current, id, ip, last_seen_at, user_agent
+        FROM sessions
+        WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+        ORDER BY last_seen_at DESC
+        ",
+    )
+    .bind(&claims.sub)
+    .bind(&claims.sid)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    Ok(Json(sessions))
+}
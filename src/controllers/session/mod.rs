@@ -0,0 +1,4 @@
+pub mod delete;
+pub mod delete_all;
+pub mod delete_others;
+pub mod get;
@@ -0,0 +1,154 @@
+use crate::storage::Storage;
+use sqlx::postgres::{PgConnection, PgPool};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+const BATCH_SIZE: i64 = 50;
+/// How long a claimed row is left alone by other workers, in case this one dies midway
+const LEASE_SECS: f64 = 5.0 * 60.0;
+const MAX_BACKOFF_SECS: i64 = 6 * 60 * 60;
+/// Advisory lock space of object keys, the hashed key goes in the second half of the lock
+const OBJECT_KEY_LOCK: i32 = 0x6f626a;
+
+/// Drains `pending_deletions`, retrying failed bucket deletes with exponential backoff. Wakes up
+/// every `interval` or as soon as `notify` is signaled after a meme gets deleted. Completed rows
+/// are kept for `retention_days`
+pub fn spawn_worker(
+    db: PgPool,
+    storage: Arc<dyn Storage>,
+    interval: Duration,
+    retention_days: u64,
+    notify: Arc<Notify>,
+) {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = run_batch(&db, storage.as_ref()).await {
+                eprintln!("Pending deletions batch failed: {}", e);
+            }
+
+            if let Err(e) = prune(&db, retention_days).await {
+                eprintln!("Pruning completed deletions failed: {}", e);
+            }
+
+            tokio::select! {
+                _ = notify.notified() => {},
+                _ = tokio::time::sleep(interval) => {},
+            }
+        }
+    });
+}
+
+/// Holds `object_key` until the transaction ends. Content addressed keys are shared by memes with
+/// identical files, so the upload storing and recording one and the worker checking and deleting
+/// it must not interleave
+pub async fn lock_object_key(conn: &mut PgConnection, object_key: &str) -> Result<(), String> {
+    sqlx::query("SELECT pg_advisory_xact_lock($1, hashtext($2))")
+        .bind(OBJECT_KEY_LOCK)
+        .bind(object_key)
+        .execute(conn)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+pub async fn run_batch(db: &PgPool, storage: &dyn Storage) -> Result<(), String> {
+    // Rows are claimed by pushing their next attempt past the lease, so every pod can run the
+    // worker without deleting an object twice and only the key being deleted stays locked
+    let due: Vec<(i32, String, i32)> = sqlx::query_as(
+        "
+        UPDATE pending_deletions
+        SET next_attempt_at = NOW() + make_interval(secs => $2)
+        WHERE id IN (
+            SELECT id
+            FROM pending_deletions
+            WHERE completed_at IS NULL
+            AND next_attempt_at <= NOW()
+            ORDER BY next_attempt_at ASC
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, object_key, attempts
+        ",
+    )
+    .bind(BATCH_SIZE)
+    .bind(LEASE_SECS)
+    .fetch_all(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    for (id, object_key, attempts) in due {
+        let mut tx = db.begin().await.map_err(|e| e.to_string())?;
+        lock_object_key(&mut tx, &object_key).await?;
+
+        // Content addressed keys are shared by memes with identical files
+        let (referenced,): (bool,) = sqlx::query_as(
+            "
+            SELECT EXISTS (SELECT 1 FROM memes WHERE object_key = $1)
+            OR EXISTS (SELECT 1 FROM meme_renditions WHERE object_key = $1)
+            ",
+        )
+        .bind(&object_key)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let result = if referenced {
+            Ok(())
+        } else {
+            storage.delete(&object_key).await
+        };
+
+        match result {
+            Ok(()) => {
+                sqlx::query(
+                    "UPDATE pending_deletions SET completed_at = NOW(), last_error = NULL WHERE id = $1",
+                )
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+            }
+            Err(e) => {
+                let backoff = 2_i64
+                    .saturating_pow(attempts as u32 + 1)
+                    .min(MAX_BACKOFF_SECS);
+
+                sqlx::query(
+                    "
+                    UPDATE pending_deletions
+                    SET
+                        attempts = attempts + 1,
+                        last_error = $2,
+                        next_attempt_at = NOW() + make_interval(secs => $3)
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .bind(&e)
+                .bind(backoff as f64)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+            }
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+async fn prune(db: &PgPool, retention_days: u64) -> Result<(), String> {
+    sqlx::query(
+        "
+        DELETE FROM pending_deletions
+        WHERE completed_at < NOW() - make_interval(days => $1)
+        ",
+    )
+    .bind(retention_days as i32)
+    .execute(db)
+    .await
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
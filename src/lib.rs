@@ -1,22 +1,26 @@
 use aws_config::SdkConfig;
 use aws_credential_types::{provider::SharedCredentialsProvider, Credentials};
 use aws_sdk_s3::{
-    config::{BehaviorVersion, Region},
+    config::{BehaviorVersion, Region, RequestChecksumCalculation},
     Client,
 };
 use axum::http::StatusCode;
 use rand::rng;
 use rand::seq::IndexedRandom;
 
+pub mod deletions;
 mod macros;
-mod models;
-
-pub async fn create_bucket_client() -> Result<Client, String> {
-    let config = models::Config::from_env().expect("Error creating Config");
+pub mod models;
+pub mod oauth;
+pub mod processing;
+pub mod reconcile;
+pub mod sessions;
+pub mod storage;
 
+pub fn create_bucket_client(config: &models::Config) -> Client {
     let credentials = Credentials::new(
-        config.bucket_key,
-        config.bucket_secret,
+        &config.bucket_key,
+        &config.bucket_secret,
         None,
         None,
         "digitalocean",
@@ -25,13 +29,20 @@ pub async fn create_bucket_client() -> Result<Client, String> {
     let credentials_provider = SharedCredentialsProvider::new(credentials);
 
     let sdk_config = SdkConfig::builder()
-        .region(Some(Region::new(config.bucket_region)))
-        .endpoint_url(config.bucket_endpoint)
+        .region(Some(Region::new(config.bucket_region.clone())))
+        .endpoint_url(&config.bucket_endpoint)
         .credentials_provider(credentials_provider)
         .behavior_version(BehaviorVersion::latest())
         .build();
 
-    Ok(Client::new(&sdk_config))
+    // Checksums are only sent when an operation requires them, S3 compatible stores often
+    // reject the ones newer SDKs add by default
+    let s3_config = aws_sdk_s3::config::Builder::from(&sdk_config)
+        .force_path_style(config.bucket_force_path_style)
+        .request_checksum_calculation(RequestChecksumCalculation::WhenRequired)
+        .build();
+
+    Client::from_conf(s3_config)
 }
 
 pub fn generate_username() -> Result<String, (StatusCode, String)> {
@@ -1,11 +1,20 @@
+mod commands;
 mod controllers;
 mod macros;
 mod middlewares;
-mod models;
 mod routes;
 
+use memelibre_server::{
+    deletions, models,
+    oauth::Providers,
+    processing::{cache::ImageCache, pool::Pool, watermark::Watermark},
+    reconcile, storage,
+};
 use sqlx::postgres::PgPoolOptions;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
 
 #[tokio::main]
 async fn main() {
@@ -17,15 +26,64 @@ async fn main() {
         .await
         .expect("Error connecting to database");
 
-    let state = Arc::new(models::AppState { config, db });
+    let storage = storage::from_config(&config)
+        .await
+        .expect("Error creating storage");
+
+    let image_cache = ImageCache::from_config(&config);
+    let oauth = Providers::from_config(&config);
+    let processing = Pool::from_config(&config);
+    let resizing = Pool::new(config.resize_workers, config.resize_queue);
+    let watermark = Watermark::from_config(&config).expect("Error creating watermark");
+
+    let state = Arc::new(models::AppState {
+        config,
+        db,
+        deletions: Arc::new(Notify::new()),
+        image_cache,
+        oauth,
+        processing,
+        resizing,
+        storage,
+        watermark,
+    });
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some((command, args)) = args.split_first() {
+        if let Err(e) = commands::run(command, args, &state).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    deletions::spawn_worker(
+        state.db.clone(),
+        state.storage.clone(),
+        Duration::from_secs(state.config.deletions_interval),
+        state.config.deletions_retention,
+        state.deletions.clone(),
+    );
+
+    if state.config.reconcile_interval > 0 {
+        reconcile::spawn_periodic(
+            state.db.clone(),
+            state.storage.clone(),
+            Duration::from_secs(state.config.reconcile_interval),
+            chrono::Duration::seconds(state.config.reconcile_grace_period as i64),
+        );
+    }
 
     let app = routes::create_route(&state);
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", state.config.port))
         .await
-        .expect("Error binding to port 3000");
+        .unwrap_or_else(|e| panic!("Error binding to port {}: {}", state.config.port, e));
 
-    axum::serve(listener, app)
-        .await
-        .expect("Error starting server");
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .expect("Error starting server");
 }
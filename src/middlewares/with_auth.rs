@@ -9,6 +9,7 @@ use axum::{
 };
 use axum_extra::extract::cookie::CookieJar;
 use jsonwebtoken::{decode, errors::ErrorKind, Algorithm, DecodingKey, Validation};
+use memelibre_server::sessions;
 use std::sync::Arc;
 
 pub async fn handler(
@@ -24,7 +25,7 @@ pub async fn handler(
 
     let claims = decode::<models::JWTClaims>(
         session_token,
-        &DecodingKey::from_secret(&state.config.jwt_secret.as_bytes()),
+        &DecodingKey::from_secret(state.config.jwt_secret.as_bytes()),
         &Validation::new(Algorithm::HS256),
     )
     .map_err(|e| {
@@ -40,6 +41,17 @@ pub async fn handler(
     })?
     .claims;
 
+    let is_active = sessions::touch(&state.db, &claims.sid)
+        .await
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    if !is_active {
+        return Err(http_error!(
+            StatusCode::UNAUTHORIZED,
+            "Session has been revoked"
+        ));
+    }
+
     req.extensions_mut().insert(claims);
     Ok(next.run(req).await)
 }
@@ -1,10 +1,26 @@
+use crate::oauth::Providers;
+use crate::processing::{cache::ImageCache, phash, pool::Pool, watermark::Watermark};
+use crate::sessions::TrustedProxy;
+use crate::storage::Storage;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgPool;
 use std::env;
+use std::sync::Arc;
+use tokio::sync::Notify;
 
 pub struct AppState {
     pub config: Config,
     pub db: PgPool,
+    /// Wakes the pending deletions worker up
+    pub deletions: Arc<Notify>,
+    pub image_cache: ImageCache,
+    pub oauth: Providers,
+    pub processing: Pool,
+    /// Separate from `processing` so resizes can't crowd uploads out
+    pub resizing: Pool,
+    pub storage: Arc<dyn Storage>,
+    pub watermark: Watermark,
 }
 
 #[derive(Serialize, sqlx::FromRow)]
@@ -19,21 +35,60 @@ pub struct CommentWithUsername {
 #[allow(dead_code)]
 pub struct Config {
     pub bucket_endpoint: String,
+    pub bucket_force_path_style: bool,
     pub bucket_key: String,
     pub bucket_name: String,
     pub bucket_object_max_size: usize,
     pub bucket_region: String,
     pub bucket_secret: String,
     pub client_url: String,
+    pub compression_avif: bool,
+    pub compression_mode: CompressionMode,
     pub compression_quality: f32,
+    pub compression_quality_avif: f32,
+    pub compression_quality_jpeg: f32,
+    pub compression_quality_min: f32,
+    pub compression_target_size: usize,
+    pub compression_target_ssim: f64,
     pub db_conn_string: String,
     pub db_max_conn: u32,
+    pub deletions_interval: u64,
+    pub deletions_retention: u64,
+    pub duplicate_action: DuplicateAction,
+    pub duplicate_max_distance: u32,
+    pub image_cache_size: usize,
+    pub image_downscale: bool,
+    pub image_max_frames: usize,
+    pub image_max_height: u32,
+    pub image_max_pixels: u64,
+    pub image_max_width: u32,
     pub jwt_secret: String,
     pub memes_pull_limit: i64,
-    pub oauth_google_client_id: String,
-    pub oauth_google_client_secret: String,
+    pub oauth_providers: Vec<OAuthProviderConfig>,
     pub oauth_redirect_uri: String,
+    pub port: u16,
+    pub presign_expiration: u64,
+    pub processing_queue: usize,
+    pub processing_workers: usize,
+    pub reconcile_grace_period: u64,
+    pub reconcile_interval: u64,
+    pub resize_queue: usize,
+    pub resize_workers: usize,
+    pub session_access_ttl: u64,
+    pub session_refresh_ttl: u64,
+    pub storage_backend: StorageBackend,
+    pub storage_local_dir: String,
+    pub storage_local_url: String,
     pub timeout_duration: u64,
+    pub trusted_proxies: Vec<TrustedProxy>,
+    pub video_max_duration: u64,
+    pub video_max_size: usize,
+    pub watermark_enabled: bool,
+    pub watermark_logo_path: Option<String>,
+    pub watermark_opacity: f32,
+    pub watermark_position: WatermarkPosition,
+    pub watermark_scale: f32,
+    pub watermark_text: String,
 }
 
 impl Config {
@@ -42,6 +97,10 @@ impl Config {
             env::var(name).map_err(|_| format!("Missing env var: {}", name))
         }
 
+        fn get_env_var_or(name: &str, default: &str) -> String {
+            env::var(name).unwrap_or_else(|_| default.to_string())
+        }
+
         fn get_and_parse_env_var<T: std::str::FromStr>(name: &str) -> Result<T, String>
         where
             T::Err: std::fmt::Display,
@@ -52,32 +111,269 @@ impl Config {
                 .map_err(|e| format!("Failed to parse {} env var: {}", name, e))
         }
 
+        fn get_and_parse_env_var_or<T: std::str::FromStr>(
+            name: &str,
+            default: T,
+        ) -> Result<T, String>
+        where
+            T::Err: std::fmt::Display,
+        {
+            match env::var(name) {
+                Ok(_) => get_and_parse_env_var(name),
+                Err(_) => Ok(default),
+            }
+        }
+
+        /// Google and GitHub come preconfigured, any other provider needs at least an issuer
+        /// supporting OIDC discovery
+        fn get_oauth_provider(name: &str) -> Result<OAuthProviderConfig, String> {
+            if !name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            {
+                return Err(format!("Invalid OAUTH_PROVIDERS env var: {}", name));
+            }
+
+            let env_name =
+                |field: &str| format!("OAUTH_{}_{}", name.to_uppercase().replace('-', "_"), field);
+            let get_optional = |field: &str, default: Option<&str>| {
+                env::var(env_name(field))
+                    .ok()
+                    .or(default.map(str::to_string))
+            };
+
+            let (issuer, authorization_url, token_url, userinfo_url, scope) = match name {
+                "google" => (
+                    Some("https://accounts.google.com"),
+                    None,
+                    None,
+                    None,
+                    "openid",
+                ),
+                "github" => (
+                    None,
+                    Some("https://github.com/login/oauth/authorize"),
+                    Some("https://github.com/login/oauth/access_token"),
+                    Some("https://api.github.com/user"),
+                    "read:user",
+                ),
+                _ => (None, None, None, None, "openid"),
+            };
+
+            // Google documents both forms for the `iss` of its ID tokens
+            let issuer_aliases = match name {
+                "google" => Some("accounts.google.com"),
+                _ => None,
+            };
+
+            // Deployments from before other providers were supported only set these
+            let (legacy_client_id, legacy_client_secret) = match name {
+                "google" => (
+                    env::var("OATH_GOOGLE_CLIENT_ID").ok(),
+                    env::var("OATH_GOOGLE_CLIENT_SECRET").ok(),
+                ),
+                _ => (None, None),
+            };
+
+            let provider = OAuthProviderConfig {
+                authorization_url: get_optional("AUTHORIZATION_URL", authorization_url),
+                client_id: get_optional("CLIENT_ID", legacy_client_id.as_deref())
+                    .ok_or_else(|| format!("Missing env var: {}", env_name("CLIENT_ID")))?,
+                client_secret: get_optional("CLIENT_SECRET", legacy_client_secret.as_deref())
+                    .ok_or_else(|| format!("Missing env var: {}", env_name("CLIENT_SECRET")))?,
+                issuer: get_optional("ISSUER", issuer),
+                issuer_aliases: get_optional("ISSUER_ALIASES", issuer_aliases)
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|alias| !alias.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+                name: name.to_string(),
+                scope: get_optional("SCOPE", Some(scope)).unwrap_or_default(),
+                token_url: get_optional("TOKEN_URL", token_url),
+                userinfo_url: get_optional("USERINFO_URL", userinfo_url),
+            };
+
+            let has_endpoints = provider.authorization_url.is_some()
+                && provider.token_url.is_some()
+                && provider.userinfo_url.is_some();
+
+            if provider.issuer.is_none() && !has_endpoints {
+                return Err(format!("Missing env var: {}", env_name("ISSUER")));
+            }
+
+            Ok(provider)
+        }
+
+        let duplicate_max_distance = get_and_parse_env_var_or("DUPLICATE_MAX_DISTANCE", 4)?;
+
+        // Candidates are looked up by an exactly matching hash band, wider distances would miss
+        if duplicate_max_distance >= phash::BANDS {
+            return Err(format!(
+                "Invalid DUPLICATE_MAX_DISTANCE env var: must be below {}",
+                phash::BANDS
+            ));
+        }
+
+        let storage_backend: StorageBackend = get_env_var_or("STORAGE_BACKEND", "s3").parse()?;
+
+        // The bucket is only mandatory when memes are actually stored in it
+        let get_bucket_env_var = |name: &str| match storage_backend {
+            StorageBackend::Local => Ok(get_env_var_or(name, "")),
+            StorageBackend::S3 => get_env_var(name),
+        };
+
         Ok(Self {
-            bucket_endpoint: get_env_var("BUCKET_ENDPOINT")?,
-            bucket_key: get_env_var("BUCKET_KEY")?,
-            bucket_name: get_env_var("BUCKET_NAME")?,
+            bucket_endpoint: get_bucket_env_var("BUCKET_ENDPOINT")?,
+            bucket_force_path_style: get_and_parse_env_var_or("BUCKET_FORCE_PATH_STYLE", false)?,
+            bucket_key: get_bucket_env_var("BUCKET_KEY")?,
+            bucket_name: get_bucket_env_var("BUCKET_NAME")?,
             bucket_object_max_size: get_and_parse_env_var("BUCKET_OBJECT_MAX_SIZE")?,
-            bucket_region: get_env_var("BUCKET_REGION")?,
-            bucket_secret: get_env_var("BUCKET_SECRET")?,
+            bucket_region: get_bucket_env_var("BUCKET_REGION")?,
+            bucket_secret: get_bucket_env_var("BUCKET_SECRET")?,
             client_url: get_env_var("CLIENT_URL")?,
+            compression_avif: get_and_parse_env_var_or("COMPRESSION_AVIF", false)?,
+            compression_mode: get_env_var_or("COMPRESSION_MODE", "fixed").parse()?,
             compression_quality: get_and_parse_env_var::<f32>("COMPRESSION_QUALITY")?
                 .clamp(0.0, 100.0),
+            compression_quality_avif: get_and_parse_env_var_or::<f32>(
+                "COMPRESSION_QUALITY_AVIF",
+                60.0,
+            )?
+            .clamp(0.0, 100.0),
+            compression_quality_jpeg: get_and_parse_env_var_or::<f32>(
+                "COMPRESSION_QUALITY_JPEG",
+                80.0,
+            )?
+            .clamp(0.0, 100.0),
+            compression_quality_min: get_and_parse_env_var_or::<f32>(
+                "COMPRESSION_QUALITY_MIN",
+                30.0,
+            )?
+            .clamp(0.0, 100.0),
+            compression_target_size: get_and_parse_env_var_or("COMPRESSION_TARGET_SIZE", 200_000)?,
+            compression_target_ssim: get_and_parse_env_var_or::<f64>(
+                "COMPRESSION_TARGET_SSIM",
+                0.95,
+            )?
+            .clamp(0.0, 1.0),
             db_conn_string: get_env_var("DB_CONN_STRING")?,
             db_max_conn: get_and_parse_env_var("DB_MAX_CONN")?,
+            deletions_interval: get_and_parse_env_var_or("DELETIONS_INTERVAL", 60)?,
+            deletions_retention: get_and_parse_env_var_or("DELETIONS_RETENTION", 7)?,
+            duplicate_action: get_env_var_or("DUPLICATE_ACTION", "reject").parse()?,
+            duplicate_max_distance,
+            image_cache_size: get_and_parse_env_var_or("IMAGE_CACHE_SIZE", 64 * 1024 * 1024)?,
+            image_downscale: get_and_parse_env_var_or("IMAGE_DOWNSCALE", true)?,
+            image_max_frames: get_and_parse_env_var_or("IMAGE_MAX_FRAMES", 500)?,
+            image_max_height: get_and_parse_env_var_or("IMAGE_MAX_HEIGHT", 8192)?,
+            image_max_pixels: get_and_parse_env_var_or("IMAGE_MAX_PIXELS", 50_000_000)?,
+            image_max_width: get_and_parse_env_var_or("IMAGE_MAX_WIDTH", 8192)?,
             jwt_secret: get_env_var("JWT_SECRET")?,
             memes_pull_limit: get_and_parse_env_var("MEMES_PULL_LIMIT")?,
-            oauth_google_client_id: get_env_var("OATH_GOOGLE_CLIENT_ID")?,
-            oauth_google_client_secret: get_env_var("OATH_GOOGLE_CLIENT_SECRET")?,
+            oauth_providers: get_env_var_or("OAUTH_PROVIDERS", "google")
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(get_oauth_provider)
+                .collect::<Result<_, _>>()?,
             oauth_redirect_uri: get_env_var("OAUTH_REDIRECT_URI")?,
+            port: get_and_parse_env_var_or("PORT", 3000)?,
+            presign_expiration: get_and_parse_env_var_or("PRESIGN_EXPIRATION", 300)?,
+            processing_queue: get_and_parse_env_var_or("PROCESSING_QUEUE", 16)?,
+            processing_workers: get_and_parse_env_var_or(
+                "PROCESSING_WORKERS",
+                std::thread::available_parallelism().map_or(1, |n| n.get()),
+            )?,
+            reconcile_grace_period: get_and_parse_env_var_or("RECONCILE_GRACE_PERIOD", 86400)?,
+            reconcile_interval: get_and_parse_env_var_or("RECONCILE_INTERVAL", 0)?,
+            resize_queue: get_and_parse_env_var_or("RESIZE_QUEUE", 16)?,
+            resize_workers: get_and_parse_env_var_or(
+                "RESIZE_WORKERS",
+                std::thread::available_parallelism().map_or(1, |n| n.get().div_ceil(2)),
+            )?,
+            session_access_ttl: get_and_parse_env_var_or("SESSION_ACCESS_TTL", 900)?,
+            session_refresh_ttl: get_and_parse_env_var_or("SESSION_REFRESH_TTL", 15 * 86400)?,
+            storage_backend,
+            storage_local_dir: get_env_var_or("STORAGE_LOCAL_DIR", "./media"),
+            storage_local_url: get_env_var_or("STORAGE_LOCAL_URL", "http://localhost:3000"),
             timeout_duration: get_and_parse_env_var("TIMEOUT_DURATION")?,
+            trusted_proxies: get_env_var_or("TRUSTED_PROXIES", "")
+                .split(',')
+                .map(str::trim)
+                .filter(|proxy| !proxy.is_empty())
+                .map(str::parse)
+                .collect::<Result<_, _>>()?,
+            video_max_duration: get_and_parse_env_var_or("VIDEO_MAX_DURATION", 60)?,
+            video_max_size: get_and_parse_env_var_or("VIDEO_MAX_SIZE", 50 * 1024 * 1024)?,
+            watermark_enabled: get_and_parse_env_var_or("WATERMARK_ENABLED", false)?,
+            watermark_logo_path: env::var("WATERMARK_LOGO_PATH").ok(),
+            watermark_opacity: get_and_parse_env_var_or::<f32>("WATERMARK_OPACITY", 0.5)?
+                .clamp(0.0, 1.0),
+            watermark_position: get_env_var_or("WATERMARK_POSITION", "bottom-right").parse()?,
+            watermark_scale: get_and_parse_env_var_or::<f32>("WATERMARK_SCALE", 0.25)?
+                .clamp(0.01, 1.0),
+            watermark_text: get_env_var_or("WATERMARK_TEXT", "memelibre.com"),
         })
     }
+
+    /// Largest upload accepted before its type is known, processing then applies the image or
+    /// video limit
+    pub fn max_upload_size(&self) -> usize {
+        self.bucket_object_max_size.max(self.video_max_size)
+    }
+}
+
+/// How the WebP quality of static images is picked
+#[derive(Clone, Copy, PartialEq)]
+pub enum CompressionMode {
+    /// Always `compression_quality`
+    Fixed,
+    /// Highest quality that fits in `compression_target_size` bytes
+    Size,
+    /// Lowest quality that keeps `compression_target_ssim` similarity to the original
+    Ssim,
+}
+
+impl std::str::FromStr for CompressionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(Self::Fixed),
+            "size" => Ok(Self::Size),
+            "ssim" => Ok(Self::Ssim),
+            _ => Err(format!("Invalid COMPRESSION_MODE env var: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DuplicateAction {
+    Flag,
+    Off,
+    Reject,
+}
+
+impl std::str::FromStr for DuplicateAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flag" => Ok(Self::Flag),
+            "off" => Ok(Self::Off),
+            "reject" => Ok(Self::Reject),
+            _ => Err(format!("Invalid DUPLICATE_ACTION env var: {}", s)),
+        }
+    }
 }
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct JWTClaims {
     pub exp: usize,
     pub is_admin: bool,
+    pub sid: String,
     pub sub: String,
     pub username: String,
 }
@@ -94,6 +390,35 @@ pub struct Meme {
     pub id: i32,
     pub image_url: String,
     pub like_count: i32,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub media: MemeMedia,
+}
+
+/// Format, size and placeholder of the stored image, null for memes the backfills haven't
+/// reached yet
+#[derive(Serialize, sqlx::FromRow)]
+pub struct MemeMedia {
+    pub blurhash: Option<String>,
+    pub byte_size: Option<i64>,
+    pub height: Option<i32>,
+    pub is_animated: Option<bool>,
+    pub media_type: String,
+    pub mime_type: Option<String>,
+    /// Frame shown before a video plays, null for images and videos without one
+    pub poster_url: Option<String>,
+    pub width: Option<i32>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct MemeRendition {
+    pub height: i32,
+    pub image_url: String,
+    #[serde(skip)]
+    pub meme_id: i32,
+    pub mime_type: String,
+    pub name: String,
+    pub width: i32,
 }
 
 #[derive(Serialize, sqlx::FromRow)]
@@ -102,6 +427,9 @@ pub struct MemeWithUsernameAndComments {
     pub id: i32,
     pub image_url: String,
     pub like_count: i32,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub media: MemeMedia,
     pub username: String,
 }
 
@@ -111,6 +439,11 @@ pub struct MemeWithUsernameAndCommentsCount {
     pub id: i32,
     pub image_url: String,
     pub like_count: i32,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub media: MemeMedia,
+    #[sqlx(skip)]
+    pub renditions: Vec<MemeRendition>,
     pub username: String,
 }
 
@@ -119,24 +452,83 @@ pub struct MemeWithUsername {
     pub id: i32,
     pub image_url: String,
     pub like_count: i32,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub media: MemeMedia,
     pub username: String,
 }
 
+/// Endpoints left unset are read from the issuer's OIDC discovery document
+#[derive(Clone)]
+pub struct OAuthProviderConfig {
+    pub authorization_url: Option<String>,
+    pub client_id: String,
+    pub client_secret: String,
+    pub issuer: Option<String>,
+    /// Other spellings of the issuer ID tokens may carry
+    pub issuer_aliases: Vec<String>,
+    pub name: String,
+    pub scope: String,
+    pub token_url: Option<String>,
+    pub userinfo_url: Option<String>,
+}
+
 #[derive(Deserialize)]
 pub struct Pagination {
     pub offset: Option<i32>,
 }
 
+#[derive(Serialize, sqlx::FromRow)]
+pub struct PendingDeletion {
+    pub attempts: i32,
+    pub created_at: DateTime<Utc>,
+    pub id: i32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub object_key: String,
+}
+
 #[derive(Serialize, sqlx::FromRow)]
 pub struct Save {
     pub meme_id: i32,
     pub user_id: String,
 }
 
+#[derive(Serialize, sqlx::FromRow)]
+pub struct Session {
+    pub created_at: DateTime<Utc>,
+    /// Whether this is the session the request was made with
+    pub current: bool,
+    pub id: String,
+    pub ip: Option<String>,
+    pub last_seen_at: DateTime<Utc>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum StorageBackend {
+    Local,
+    S3,
+}
+
+impl std::str::FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(Self::Local),
+            "s3" => Ok(Self::S3),
+            _ => Err(format!("Invalid STORAGE_BACKEND env var: {}", s)),
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct TokenResponse {
     pub access_token: String,
     pub expires_in: Option<u64>,
+    /// Only sent by OIDC providers
+    pub id_token: Option<String>,
     pub refresh_token: Option<String>,
     pub scope: Option<String>,
     pub token_type: String,
@@ -148,3 +540,27 @@ pub struct User {
     pub is_admin: bool,
     pub username: String,
 }
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum WatermarkPosition {
+    BottomLeft,
+    BottomRight,
+    Center,
+    TopLeft,
+    TopRight,
+}
+
+impl std::str::FromStr for WatermarkPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bottom-left" => Ok(Self::BottomLeft),
+            "bottom-right" => Ok(Self::BottomRight),
+            "center" => Ok(Self::Center),
+            "top-left" => Ok(Self::TopLeft),
+            "top-right" => Ok(Self::TopRight),
+            _ => Err(format!("Invalid WATERMARK_POSITION env var: {}", s)),
+        }
+    }
+}
@@ -0,0 +1,392 @@
+use crate::models::{Config, OAuthProviderConfig, TokenResponse};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use reqwest::{header, Client, Url};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::{OnceCell, RwLock};
+
+#[derive(Deserialize)]
+struct Discovery {
+    authorization_endpoint: String,
+    issuer: String,
+    jwks_uri: Option<String>,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+}
+
+pub struct Endpoints {
+    pub authorization: String,
+    /// Issuer ID tokens must name, exactly as the discovery document spells it
+    pub issuer: Option<String>,
+    pub jwks: Option<String>,
+    pub token: String,
+    pub userinfo: String,
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    nonce: Option<String>,
+    sub: String,
+}
+
+/// Error response of the token endpoint (RFC 6749 section 5.2), GitHub sends it with a 200
+#[derive(Deserialize)]
+struct TokenError {
+    error: String,
+    error_description: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TokenReply {
+    Tokens(TokenResponse),
+    Error(TokenError),
+}
+
+/// Token endpoint errors that point at this server's configuration rather than at the code
+const CONFIGURATION_ERRORS: [&str; 5] = [
+    "incorrect_client_credentials",
+    "invalid_client",
+    "redirect_uri_mismatch",
+    "unauthorized_client",
+    "unsupported_grant_type",
+];
+
+pub enum ExchangeError {
+    /// The provider turned the code down, it was already used, expired or issued to another
+    /// PKCE challenge
+    Rejected(String),
+    /// The provider couldn't be reached, is misconfigured or answered with something else
+    Failed(String),
+}
+
+/// Symmetric algorithms would verify with the client secret, only the provider's keys are trusted
+const ID_TOKEN_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// S256 PKCE challenge of a code verifier
+pub fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+/// OIDC userinfo carries `sub`, plain OAuth APIs like GitHub's an `id` that may be a number
+#[derive(Deserialize)]
+struct UserInfo {
+    id: Option<Subject>,
+    sub: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Subject {
+    Number(u64),
+    String(String),
+}
+
+pub struct Provider {
+    pub config: OAuthProviderConfig,
+    /// Discovered on first use so an unreachable provider doesn't keep the server from starting
+    endpoints: OnceCell<Endpoints>,
+    http: Client,
+    jwks: RwLock<Option<JwkSet>>,
+}
+
+impl Provider {
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Providers with an issuer send an ID token the user id is read from, the others are
+    /// asked for it through their userinfo endpoint
+    pub fn is_oidc(&self) -> bool {
+        self.config.issuer.is_some()
+    }
+
+    pub async fn endpoints(&self) -> Result<&Endpoints, String> {
+        self.endpoints.get_or_try_init(|| self.discover()).await
+    }
+
+    async fn discover(&self) -> Result<Endpoints, String> {
+        let discovery = match &self.config.issuer {
+            Some(issuer) => {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    issuer.trim_end_matches('/')
+                );
+                let discovery: Discovery = get_json(self.http.get(&url)).await?;
+
+                if discovery.issuer.trim_end_matches('/') != issuer.trim_end_matches('/') {
+                    return Err(format!(
+                        "Discovery document of {} is for issuer {}",
+                        issuer, discovery.issuer
+                    ));
+                }
+
+                Some(discovery)
+            }
+            None => None,
+        };
+
+        let endpoint = |configured: &Option<String>, discovered: Option<&String>, name: &str| {
+            configured
+                .clone()
+                .or_else(|| discovered.cloned())
+                .ok_or_else(|| format!("Provider {} has no {} endpoint", self.name(), name))
+        };
+
+        Ok(Endpoints {
+            authorization: endpoint(
+                &self.config.authorization_url,
+                discovery.as_ref().map(|d| &d.authorization_endpoint),
+                "authorization",
+            )?,
+            issuer: discovery.as_ref().map(|d| d.issuer.clone()),
+            jwks: discovery.as_ref().and_then(|d| d.jwks_uri.clone()),
+            token: endpoint(
+                &self.config.token_url,
+                discovery.as_ref().map(|d| &d.token_endpoint),
+                "token",
+            )?,
+            userinfo: endpoint(
+                &self.config.userinfo_url,
+                discovery
+                    .as_ref()
+                    .and_then(|d| d.userinfo_endpoint.as_ref()),
+                "userinfo",
+            )?,
+        })
+    }
+
+    /// `nonce` is only sent to OIDC providers, it comes back inside the ID token
+    pub async fn authorization_url(
+        &self,
+        redirect_uri: &str,
+        state: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<Url, String> {
+        let endpoints = self.endpoints().await?;
+        let code_challenge = code_challenge(code_verifier);
+
+        let mut params = vec![
+            ("client_id", self.config.client_id.as_str()),
+            ("code_challenge", code_challenge.as_str()),
+            ("code_challenge_method", "S256"),
+            ("redirect_uri", redirect_uri),
+            ("response_type", "code"),
+            ("scope", self.config.scope.as_str()),
+            ("state", state),
+        ];
+
+        if self.is_oidc() {
+            params.push(("nonce", nonce));
+        }
+
+        Url::parse_with_params(&endpoints.authorization, &params).map_err(|e| e.to_string())
+    }
+
+    pub async fn exchange_code(
+        &self,
+        redirect_uri: &str,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<TokenResponse, ExchangeError> {
+        let endpoints = self.endpoints().await.map_err(ExchangeError::Failed)?;
+
+        let params = [
+            ("client_id", self.config.client_id.as_str()),
+            ("client_secret", self.config.client_secret.as_str()),
+            ("code", code),
+            ("code_verifier", code_verifier),
+            ("grant_type", "authorization_code"),
+            ("redirect_uri", redirect_uri),
+        ];
+
+        // GitHub answers with a form encoded body unless asked for JSON
+        let response = self
+            .http
+            .post(&endpoints.token)
+            .header(header::ACCEPT, "application/json")
+            .form(&params)
+            .send()
+            .await
+            .map_err(|e| ExchangeError::Failed(e.to_string()))?;
+
+        let status = response.status();
+
+        match response.json::<TokenReply>().await {
+            Ok(TokenReply::Tokens(tokens)) if status.is_success() => Ok(tokens),
+            Ok(TokenReply::Error(e)) => {
+                let message = match e.error_description {
+                    Some(description) => format!("{}: {}", e.error, description),
+                    None => e.error.clone(),
+                };
+
+                match CONFIGURATION_ERRORS.contains(&e.error.as_str()) {
+                    true => Err(ExchangeError::Failed(message)),
+                    false => Err(ExchangeError::Rejected(message)),
+                }
+            }
+            Ok(TokenReply::Tokens(_)) => Err(ExchangeError::Failed(status.to_string())),
+            Err(e) => Err(ExchangeError::Failed(format!("{}: {}", status, e))),
+        }
+    }
+
+    /// Verifies the ID token against the provider's published keys and returns its subject
+    pub async fn verify_id_token(&self, id_token: &str, nonce: &str) -> Result<String, String> {
+        let endpoints = self.endpoints().await?;
+        let issuer = endpoints
+            .issuer
+            .as_ref()
+            .ok_or_else(|| format!("Provider {} has no issuer", self.name()))?;
+
+        let header = decode_header(id_token).map_err(|e| e.to_string())?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(format!("Unsupported ID token algorithm {:?}", header.alg));
+        }
+
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_issuer(
+            &std::iter::once(issuer)
+                .chain(&self.config.issuer_aliases)
+                .collect::<Vec<_>>(),
+        );
+        validation.set_required_spec_claims(&["aud", "exp", "iss", "sub"]);
+
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|e| e.to_string())?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err("ID token nonce mismatch".to_string());
+        }
+
+        Ok(claims.sub)
+    }
+
+    /// Keys are cached, an unknown key id means the provider rotated them and they are fetched
+    /// again
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<DecodingKey, String> {
+        fn find<'a>(jwks: &'a JwkSet, kid: Option<&str>) -> Option<&'a Jwk> {
+            match kid {
+                Some(kid) => jwks.find(kid),
+                None if jwks.keys.len() == 1 => jwks.keys.first(),
+                None => None,
+            }
+        }
+
+        if let Some(jwks) = self.jwks.read().await.as_ref() {
+            if let Some(jwk) = find(jwks, kid) {
+                return DecodingKey::from_jwk(jwk).map_err(|e| e.to_string());
+            }
+        }
+
+        let jwks_uri = self
+            .endpoints()
+            .await?
+            .jwks
+            .as_ref()
+            .ok_or_else(|| format!("Provider {} has no JWKS endpoint", self.name()))?;
+
+        let jwks: JwkSet = get_json(self.http.get(jwks_uri)).await?;
+        let key = find(&jwks, kid)
+            .ok_or_else(|| format!("Provider {} has no key {:?}", self.name(), kid))
+            .and_then(|jwk| DecodingKey::from_jwk(jwk).map_err(|e| e.to_string()));
+
+        *self.jwks.write().await = Some(jwks);
+
+        key
+    }
+
+    /// The provider's stable id for the user, for providers without ID tokens
+    pub async fn subject(&self, access_token: &str) -> Result<String, String> {
+        let endpoints = self.endpoints().await?;
+
+        let user_info: UserInfo = get_json(
+            self.http
+                .get(&endpoints.userinfo)
+                .header(header::ACCEPT, "application/json")
+                .bearer_auth(access_token),
+        )
+        .await?;
+
+        match (user_info.sub, user_info.id) {
+            (Some(sub), _) => Ok(sub),
+            (None, Some(Subject::String(id))) => Ok(id),
+            (None, Some(Subject::Number(id))) => Ok(id.to_string()),
+            (None, None) => Err(format!("Provider {} returned no subject", self.name())),
+        }
+    }
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(
+    request: reqwest::RequestBuilder,
+) -> Result<T, String> {
+    let response = request.send().await.map_err(|e| e.to_string())?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        return Err(format!("{}: {}", status, body));
+    }
+
+    response.json().await.map_err(|e| e.to_string())
+}
+
+pub struct Providers {
+    providers: Vec<Provider>,
+}
+
+impl Providers {
+    pub fn from_config(config: &Config) -> Self {
+        // GitHub's API rejects requests without a user agent
+        let http = Client::builder()
+            .user_agent(concat!("memelibre/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .unwrap_or_default();
+
+        Self {
+            providers: config
+                .oauth_providers
+                .iter()
+                .map(|config| Provider {
+                    config: config.clone(),
+                    endpoints: OnceCell::new(),
+                    http: http.clone(),
+                    jwks: RwLock::new(None),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Provider> {
+        self.providers
+            .iter()
+            .find(|provider| provider.name() == name)
+    }
+
+    /// The first configured provider, what `/api/auth/login` without a provider uses
+    pub fn default_provider(&self) -> Option<&Provider> {
+        self.providers.first()
+    }
+}
@@ -0,0 +1,111 @@
+use crate::models::{CompressionMode, Config};
+use image::{DynamicImage, GrayImage, ImageFormat};
+use webp::Encoder;
+
+/// Picks the WebP quality of a static image for `compression_mode`, searching between
+/// `compression_quality_min` and `compression_quality`. Text heavy screenshots need a much
+/// higher quality than photos to look the same
+pub fn quality(img: &DynamicImage, config: &Config) -> f32 {
+    if config.compression_mode == CompressionMode::Fixed {
+        return config.compression_quality;
+    }
+
+    let max = config.compression_quality as u8;
+    let min = (config.compression_quality_min as u8).min(max);
+
+    let rgba = img.to_rgba8();
+    let encode =
+        |quality: u8| Encoder::from_rgba(&rgba, rgba.width(), rgba.height()).encode(quality as f32);
+
+    let quality = match config.compression_mode {
+        CompressionMode::Fixed => max,
+        CompressionMode::Size => {
+            match first_passing(min, max, |quality| {
+                encode(quality).len() > config.compression_target_size
+            }) {
+                Some(too_large) => too_large.saturating_sub(1).max(min),
+                None => max,
+            }
+        }
+        CompressionMode::Ssim => {
+            let original = img.to_luma8();
+            first_passing(min, max, |quality| {
+                image::load_from_memory_with_format(&encode(quality), ImageFormat::WebP)
+                    .map_or(0.0, |encoded| ssim(&original, &encoded.to_luma8()))
+                    >= config.compression_target_ssim
+            })
+            .unwrap_or(max)
+        }
+    };
+
+    quality as f32
+}
+
+/// Binary search for the lowest quality `check` passes at, assuming it keeps passing above it.
+/// Every step is a full encode, so this is what keeps the search to a handful of them
+fn first_passing(min: u8, max: u8, mut check: impl FnMut(u8) -> bool) -> Option<u8> {
+    let (mut low, mut high) = (min, max);
+    let mut passing = None;
+
+    while low <= high {
+        let quality = low + (high - low) / 2;
+
+        if check(quality) {
+            passing = Some(quality);
+            match quality.checked_sub(1) {
+                Some(below) => high = below,
+                None => break,
+            }
+        } else {
+            low = quality + 1;
+        }
+    }
+
+    passing
+}
+
+/// Mean structural similarity of 8x8 luma blocks, 1.0 for identical images
+fn ssim(a: &GrayImage, b: &GrayImage) -> f64 {
+    const BLOCK: u32 = 8;
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let (width, height) = a.dimensions();
+    if b.dimensions() != (width, height) {
+        return 0.0;
+    }
+
+    let mut total = 0.0;
+    let mut blocks = 0;
+
+    for block_y in (0..height / BLOCK).map(|y| y * BLOCK) {
+        for block_x in (0..width / BLOCK).map(|x| x * BLOCK) {
+            let pixels = || {
+                (block_y..block_y + BLOCK).flat_map(move |y| {
+                    (block_x..block_x + BLOCK)
+                        .map(move |x| (a.get_pixel(x, y)[0] as f64, b.get_pixel(x, y)[0] as f64))
+                })
+            };
+
+            let n = (BLOCK * BLOCK) as f64;
+            let (sum_a, sum_b) = pixels().fold((0.0, 0.0), |(sa, sb), (pa, pb)| (sa + pa, sb + pb));
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+
+            let (var_a, var_b, covariance) =
+                pixels().fold((0.0, 0.0, 0.0), |(va, vb, cov), (pa, pb)| {
+                    let (da, db) = (pa - mean_a, pb - mean_b);
+                    (va + da * da, vb + db * db, cov + da * db)
+                });
+            let (var_a, var_b, covariance) = (var_a / n, var_b / n, covariance / n);
+
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            blocks += 1;
+        }
+    }
+
+    match blocks {
+        0 => 1.0,
+        blocks => total / blocks as f64,
+    }
+}
@@ -0,0 +1,58 @@
+use crate::models::Config;
+use bytes::Bytes;
+use lru::LruCache;
+use std::sync::Mutex;
+
+/// Least recently used encoded images, bounded by their total size rather than their count
+/// since a thumbnail and a full size AVIF differ by orders of magnitude
+pub struct ImageCache {
+    inner: Mutex<Inner>,
+    max_bytes: usize,
+}
+
+struct Inner {
+    bytes: usize,
+    entries: LruCache<String, Bytes>,
+}
+
+impl ImageCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                bytes: 0,
+                entries: LruCache::unbounded(),
+            }),
+            max_bytes,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.image_cache_size)
+    }
+
+    pub fn get(&self, key: &str) -> Option<Bytes> {
+        self.inner.lock().ok()?.entries.get(key).cloned()
+    }
+
+    pub fn insert(&self, key: String, data: Bytes) {
+        if data.len() > self.max_bytes {
+            return;
+        }
+
+        let Ok(mut inner) = self.inner.lock() else {
+            return;
+        };
+
+        inner.bytes += data.len();
+        if let Some(replaced) = inner.entries.put(key, data) {
+            inner.bytes -= replaced.len();
+        }
+
+        while inner.bytes > self.max_bytes {
+            match inner.entries.pop_lru() {
+                Some((_, evicted)) => inner.bytes -= evicted.len(),
+                None => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,389 @@
+use crate::http_error;
+use crate::models::Config;
+use crate::processing::{
+    check_dimensions, limits, phash, placeholder, watermark::Watermark, ProcessedMeme, Rendition,
+};
+use axum::http::StatusCode;
+use image::{
+    codecs::gif::{GifDecoder, GifEncoder, Repeat},
+    imageops::{self, FilterType},
+    AnimationDecoder, DynamicImage, Frame, ImageDecoder,
+};
+use libwebp_sys::{
+    WebPAnimEncoderAdd, WebPAnimEncoderAssemble, WebPAnimEncoderDelete, WebPAnimEncoderNewInternal,
+    WebPAnimEncoderOptions, WebPAnimEncoderOptionsInitInternal, WebPConfig, WebPData,
+    WebPDataClear, WebPPicture, WebPPictureFree, WebPPictureImportRGBA, WEBP_MUX_ABI_VERSION,
+};
+use std::io::Cursor;
+
+pub fn process(
+    file_data: &[u8],
+    config: &Config,
+    watermark: Option<&Watermark>,
+) -> Result<ProcessedMeme, (StatusCode, String)> {
+    let mut decoder = GifDecoder::new(Cursor::new(file_data))
+        .map_err(|_| http_error!(StatusCode::BAD_REQUEST, "Invalid image format"))?;
+
+    decoder
+        .set_limits(limits(config))
+        .map_err(|_| http_error!(StatusCode::PAYLOAD_TOO_LARGE))?;
+
+    let (canvas_width, canvas_height) = decoder.dimensions();
+    check_dimensions(config, canvas_width, canvas_height, 1)?;
+
+    let downscale =
+        canvas_width > config.image_max_width || canvas_height > config.image_max_height;
+
+    // Frames are checked as they are decoded so a bomb never gets fully buffered
+    let mut frames = Vec::new();
+    for frame in decoder.into_frames() {
+        let frame =
+            frame.map_err(|_| http_error!(StatusCode::BAD_REQUEST, "Invalid image format"))?;
+        check_dimensions(config, canvas_width, canvas_height, frames.len() + 1)?;
+
+        if downscale {
+            let delay = frame.delay();
+            let buffer = DynamicImage::ImageRgba8(frame.into_buffer())
+                .resize(
+                    config.image_max_width,
+                    config.image_max_height,
+                    FilterType::Lanczos3,
+                )
+                .to_rgba8();
+            frames.push(Frame::from_parts(buffer, 0, 0, delay));
+        } else {
+            frames.push(frame);
+        }
+    }
+
+    let first_frame = frames
+        .first()
+        .ok_or_else(|| http_error!(StatusCode::BAD_REQUEST, "Invalid image format"))?;
+
+    let (width, height) = first_frame.buffer().dimensions();
+    let first_image = DynamicImage::ImageRgba8(first_frame.buffer().clone());
+    let phash = phash::dhash(&first_image);
+    let blurhash = placeholder::blurhash(&first_image);
+    let repeat = repeat(file_data);
+
+    if let Some(watermark) = watermark {
+        // Every frame has the same size, so the mark is scaled only once
+        let (mark, x, y) = watermark.render(width, height);
+        for frame in frames.iter_mut() {
+            imageops::overlay(frame.buffer_mut(), &mark, x, y);
+        }
+    }
+
+    let original = if watermark.is_some() || downscale {
+        encode_gif(&frames, repeat)?
+    } else {
+        match strip_metadata(file_data) {
+            Some(data) => data,
+            None => encode_gif(&frames, repeat)?,
+        }
+    };
+
+    // Animated WebP is not always smaller, e.g. for tiny or heavily dithered GIFs
+    let (data, content_type, extension) =
+        match encode_animated_webp(&frames, repeat, config.compression_quality) {
+            Some(webp_data) if webp_data.len() < original.len() => {
+                (webp_data, "image/webp", "webp")
+            }
+            _ => (original, "image/gif", "gif"),
+        };
+
+    Ok(ProcessedMeme {
+        animated: frames.len() > 1,
+        blurhash,
+        content_type,
+        extension,
+        media_type: "image",
+        phash: Some(phash),
+        poster: Vec::new(),
+        quality: (content_type == "image/webp").then_some(config.compression_quality),
+        renditions: vec![Rendition {
+            content_type,
+            data,
+            extension,
+            height,
+            name: "full",
+            width,
+        }],
+    })
+}
+
+/// Goes to libwebp directly, the webp crate closes the animation at timestamp 0, which libwebp
+/// rejects and then gives the last frame the average delay of the others
+fn encode_animated_webp(frames: &[Frame], repeat: Option<Repeat>, quality: f32) -> Option<Vec<u8>> {
+    let first_frame = frames.first()?;
+    let (width, height) = first_frame.buffer().dimensions();
+
+    let mut config = WebPConfig::new().ok()?;
+    config.quality = quality;
+
+    let mut options = std::mem::MaybeUninit::<WebPAnimEncoderOptions>::uninit();
+    // SAFETY: the options are only read once libwebp has initialized them
+    let mut options = unsafe {
+        if WebPAnimEncoderOptionsInitInternal(options.as_mut_ptr(), WEBP_MUX_ABI_VERSION as i32)
+            == 0
+        {
+            return None;
+        }
+        options.assume_init()
+    };
+
+    // WebP counts every play, 0 meaning forever, where GIFs count the repeats after the first
+    options.anim_params.loop_count = match repeat {
+        None => 1,
+        Some(Repeat::Infinite) => 0,
+        Some(Repeat::Finite(n)) => (n as i32 + 1).min(u16::MAX as i32),
+    };
+
+    // SAFETY: the encoder is deleted on every path out, pictures are freed right after being
+    // added since the encoder copies them, and the assembled data is copied before being cleared
+    unsafe {
+        let encoder = WebPAnimEncoderNewInternal(
+            width as i32,
+            height as i32,
+            &options,
+            WEBP_MUX_ABI_VERSION as i32,
+        );
+        if encoder.is_null() {
+            return None;
+        }
+
+        // Frames are added at the timestamp they start at, the animation is closed at the one the
+        // last frame ends at so it keeps its own delay
+        let mut timestamp = 0;
+        for frame in frames {
+            let Ok(mut picture) = WebPPicture::new() else {
+                WebPAnimEncoderDelete(encoder);
+                return None;
+            };
+            picture.use_argb = 1;
+            picture.width = width as i32;
+            picture.height = height as i32;
+
+            let added = WebPPictureImportRGBA(
+                &mut picture,
+                frame.buffer().as_raw().as_ptr(),
+                width as i32 * 4,
+            ) != 0
+                && WebPAnimEncoderAdd(encoder, &mut picture, timestamp, &config) != 0;
+            WebPPictureFree(&mut picture);
+
+            if !added {
+                WebPAnimEncoderDelete(encoder);
+                return None;
+            }
+
+            // libwebp needs timestamps to go up, GIF frames without a delay last a millisecond
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            timestamp += ((numer / denom.max(1)) as i32).max(1);
+        }
+
+        let mut webp_data = WebPData::default();
+        let assembled = WebPAnimEncoderAdd(encoder, std::ptr::null_mut(), timestamp, &config) != 0
+            && WebPAnimEncoderAssemble(encoder, &mut webp_data) != 0;
+        WebPAnimEncoderDelete(encoder);
+
+        let data =
+            assembled.then(|| std::slice::from_raw_parts(webp_data.bytes, webp_data.size).to_vec());
+        WebPDataClear(&mut webp_data);
+        data
+    }
+}
+
+fn encode_gif(frames: &[Frame], repeat: Option<Repeat>) -> Result<Vec<u8>, (StatusCode, String)> {
+    let mut data = Vec::new();
+
+    {
+        let mut encoder = GifEncoder::new(&mut data);
+        // Without a loop extension the GIF plays once, like the source did
+        if let Some(repeat) = repeat {
+            encoder
+                .set_repeat(repeat)
+                .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+        }
+        encoder
+            .encode_frames(frames.iter().cloned())
+            .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+    }
+
+    Ok(data)
+}
+
+/// Copies the GIF block by block, keeping only what's needed to play it: frames, their graphic
+/// control extensions and the NETSCAPE2.0 loop extension. Comments, XMP and any other
+/// application extension are dropped. Returns `None` if the block structure can't be walked
+fn strip_metadata(file_data: &[u8]) -> Option<Vec<u8>> {
+    fn color_table_len(flags: u8) -> usize {
+        if flags & 0x80 != 0 {
+            3 << ((flags & 0x07) + 1)
+        } else {
+            0
+        }
+    }
+
+    fn skip_sub_blocks(file_data: &[u8], mut pos: usize) -> Option<usize> {
+        loop {
+            let len = *file_data.get(pos)? as usize;
+            pos += 1;
+            if len == 0 {
+                return Some(pos);
+            }
+            pos += len;
+        }
+    }
+
+    // Header plus logical screen descriptor, followed by the optional global color table
+    let mut pos = 13 + color_table_len(*file_data.get(10)?);
+    let mut data = Vec::with_capacity(file_data.len());
+    data.extend_from_slice(file_data.get(..pos)?);
+
+    loop {
+        match *file_data.get(pos)? {
+            0x21 => {
+                let label = *file_data.get(pos + 1)?;
+                let end = skip_sub_blocks(file_data, pos + 2)?;
+                let keep = match label {
+                    0xF9 => true,
+                    0xFF => file_data.get(pos + 2..pos + 14) == Some(b"\x0BNETSCAPE2.0"),
+                    _ => false,
+                };
+                if keep {
+                    data.extend_from_slice(file_data.get(pos..end)?);
+                }
+                pos = end;
+            }
+            0x2C => {
+                let flags = *file_data.get(pos + 9)?;
+                // Image descriptor, local color table and LZW minimum code size
+                let image_data = pos + 10 + color_table_len(flags) + 1;
+                let end = skip_sub_blocks(file_data, image_data)?;
+                data.extend_from_slice(file_data.get(pos..end)?);
+                pos = end;
+            }
+            0x3B => {
+                data.push(0x3B);
+                return Some(data);
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Reads the NETSCAPE2.0 application extension, `None` for GIFs without it, which play only once
+fn repeat(file_data: &[u8]) -> Option<Repeat> {
+    const NETSCAPE: &[u8] = b"\x21\xFF\x0BNETSCAPE2.0\x03\x01";
+
+    file_data
+        .windows(NETSCAPE.len() + 2)
+        .find(|window| window.starts_with(NETSCAPE))
+        .map(|window| {
+            match u16::from_le_bytes([window[NETSCAPE.len()], window[NETSCAPE.len() + 1]]) {
+                0 => Repeat::Infinite,
+                n => Repeat::Finite(n),
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{codecs::webp::WebPDecoder, Delay, Rgba, RgbaImage};
+
+    const COMMENT: &[u8] = b"\x21\xFE\x05hello\x00";
+    const XMP: &[u8] = b"\x21\xFF\x0BXMP DataXMP\x0C<x:xmpmeta/>\x00";
+    const NETSCAPE: &[u8] = b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00";
+
+    /// 1x1 GIF with a two color global table, looping forever, with a comment and XMP around
+    /// its only frame
+    fn gif_with_metadata() -> Vec<u8> {
+        [
+            b"GIF89a\x01\x00\x01\x00\x80\x00\x00".as_slice(),
+            b"\x00\x00\x00\xFF\xFF\xFF",
+            NETSCAPE,
+            COMMENT,
+            b"\x21\xF9\x04\x00\x0A\x00\x00\x00",
+            XMP,
+            b"\x2C\x00\x00\x00\x00\x01\x00\x01\x00\x00",
+            b"\x02\x02\x44\x01\x00",
+            b"\x3B",
+        ]
+        .concat()
+    }
+
+    fn contains(data: &[u8], needle: &[u8]) -> bool {
+        data.windows(needle.len()).any(|window| window == needle)
+    }
+
+    #[test]
+    fn strip_metadata_drops_comments_and_xmp_but_keeps_looping() {
+        let file_data = gif_with_metadata();
+        assert!(GifDecoder::new(Cursor::new(&file_data)).is_ok());
+
+        let stripped = strip_metadata(&file_data).unwrap();
+
+        assert!(!contains(&stripped, COMMENT));
+        assert!(!contains(&stripped, b"XMP DataXMP"));
+        assert!(contains(&stripped, NETSCAPE));
+        assert_eq!(stripped.len(), file_data.len() - COMMENT.len() - XMP.len());
+        assert!(matches!(repeat(&stripped), Some(Repeat::Infinite)));
+
+        let frames = GifDecoder::new(Cursor::new(stripped))
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].delay().numer_denom_ms(), (100, 1));
+    }
+
+    #[test]
+    fn gif_without_a_loop_extension_plays_once() {
+        let file_data = gif_with_metadata();
+        let start = file_data
+            .windows(NETSCAPE.len())
+            .position(|window| window == NETSCAPE)
+            .unwrap();
+        let file_data = [&file_data[..start], &file_data[start + NETSCAPE.len()..]].concat();
+
+        let stripped = strip_metadata(&file_data).unwrap();
+        assert!(repeat(&stripped).is_none());
+    }
+
+    #[test]
+    fn strip_metadata_gives_up_on_truncated_gifs() {
+        let file_data = gif_with_metadata();
+
+        for len in 0..file_data.len() {
+            assert!(strip_metadata(&file_data[..len]).is_none(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn animated_webp_keeps_every_frame_delay() {
+        let delays = [100, 100, 3000];
+        let frames: Vec<Frame> = delays
+            .iter()
+            .enumerate()
+            .map(|(i, &delay)| {
+                let buffer = RgbaImage::from_pixel(8, 8, Rgba([i as u8 * 100, 0, 0, 255]));
+                Frame::from_parts(buffer, 0, 0, Delay::from_numer_denom_ms(delay, 1))
+            })
+            .collect();
+
+        let data = encode_animated_webp(&frames, Some(Repeat::Infinite), 75.0).unwrap();
+
+        let decoded: Vec<u32> = WebPDecoder::new(Cursor::new(data))
+            .unwrap()
+            .into_frames()
+            .map(|frame| {
+                let (numer, denom) = frame.unwrap().delay().numer_denom_ms();
+                numer / denom
+            })
+            .collect();
+        assert_eq!(decoded, delays);
+    }
+}
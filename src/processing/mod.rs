@@ -0,0 +1,270 @@
+use crate::http_error;
+use crate::models::Config;
+use axum::http::StatusCode;
+use image::{
+    codecs::{gif::GifDecoder, webp::WebPDecoder},
+    imageops::FilterType,
+    AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits,
+};
+use resize::Format;
+use std::borrow::Cow;
+use std::io::Cursor;
+use watermark::Watermark;
+use webp::Encoder;
+
+pub mod adaptive;
+pub mod cache;
+pub mod gif;
+pub mod phash;
+pub mod placeholder;
+pub mod pool;
+pub mod resize;
+pub mod video;
+pub mod watermark;
+
+/// Width bounds of every rendition produced on upload, `None` keeps the original size
+pub const RENDITIONS: [(&str, Option<u32>); 3] = [
+    ("thumbnail", Some(320)),
+    ("feed", Some(720)),
+    ("full", None),
+];
+
+pub struct Rendition {
+    pub content_type: &'static str,
+    pub data: Vec<u8>,
+    pub extension: &'static str,
+    pub height: u32,
+    pub name: &'static str,
+    pub width: u32,
+}
+
+pub struct ProcessedMeme {
+    pub animated: bool,
+    pub blurhash: Option<String>,
+    pub content_type: &'static str,
+    pub extension: &'static str,
+    pub media_type: &'static str,
+    /// `None` for videos without a poster, there is nothing to hash
+    pub phash: Option<u64>,
+    /// WebP quality the meme was encoded at, `None` when it was stored as uploaded
+    pub quality: Option<f32>,
+    /// WebP renditions of the frame shown before a video plays, empty for images
+    pub poster: Vec<Rendition>,
+    pub renditions: Vec<Rendition>,
+}
+
+pub fn process(
+    file_data: &[u8],
+    config: &Config,
+    watermark: Option<&Watermark>,
+) -> Result<ProcessedMeme, (StatusCode, String)> {
+    if file_data.len() > config.bucket_object_max_size {
+        return Err(http_error!(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    let reader = ImageReader::new(Cursor::new(file_data))
+        .with_guessed_format()
+        .map_err(|_| http_error!(StatusCode::BAD_REQUEST, "Invalid image format"))?;
+
+    if reader.format() == Some(ImageFormat::Gif) {
+        return gif::process(file_data, config, watermark);
+    }
+
+    let img = decode(reader, config)?;
+
+    Ok(process_still(img, config, watermark))
+}
+
+/// Decodes the first frame of any supported format within the configured limits
+fn decode(
+    mut reader: ImageReader<Cursor<&[u8]>>,
+    config: &Config,
+) -> Result<DynamicImage, (StatusCode, String)> {
+    reader.limits(limits(config));
+
+    let mut decoder = reader
+        .into_decoder()
+        .map_err(|_| http_error!(StatusCode::BAD_REQUEST, "Invalid image format"))?;
+
+    let (width, height) = decoder.dimensions();
+    check_dimensions(config, width, height, 1)?;
+
+    let orientation = decoder
+        .orientation()
+        .map_err(|_| http_error!(StatusCode::BAD_REQUEST, "Invalid image format"))?;
+
+    // Only pixels make it into the re-encoded WebP, so EXIF, XMP and ICC data are dropped here
+    let mut img = DynamicImage::from_decoder(decoder)
+        .map_err(|_| http_error!(StatusCode::BAD_REQUEST, "Invalid image format"))?;
+    img.apply_orientation(orientation);
+
+    Ok(img)
+}
+
+fn process_still(
+    mut img: DynamicImage,
+    config: &Config,
+    watermark: Option<&Watermark>,
+) -> ProcessedMeme {
+    if img.width() > config.image_max_width || img.height() > config.image_max_height {
+        img = img.resize(
+            config.image_max_width,
+            config.image_max_height,
+            FilterType::Lanczos3,
+        );
+    }
+
+    // Hashed before watermarking so reposts of our own watermarked memes still match
+    let phash = phash::dhash(&img);
+    let blurhash = placeholder::blurhash(&img);
+
+    if let Some(watermark) = watermark {
+        let mut rgba = img.to_rgba8();
+        watermark.apply(&mut rgba);
+        img = DynamicImage::ImageRgba8(rgba);
+    }
+
+    let quality = adaptive::quality(&img, config);
+    let mut renditions = Vec::new();
+
+    for &(name, max_width) in RENDITIONS.iter() {
+        let resized = match max_width {
+            Some(max_width) if img.width() > max_width => {
+                Cow::Owned(img.resize(max_width, u32::MAX, FilterType::Lanczos3))
+            }
+            Some(_) => continue,
+            None => Cow::Borrowed(&img),
+        };
+
+        renditions.push(encode_webp(name, &resized, quality));
+
+        // AVIF is an extra clients opt into, an upload never fails because of it
+        if config.compression_avif {
+            match resize::encode(&resized, Format::Avif, Format::Avif.default_quality(config)) {
+                Ok(data) => renditions.push(Rendition {
+                    content_type: Format::Avif.content_type(),
+                    data,
+                    extension: Format::Avif.extension(),
+                    height: resized.height(),
+                    name,
+                    width: resized.width(),
+                }),
+                Err((_, e)) => eprintln!("Failed to encode {} as AVIF: {}", name, e),
+            }
+        }
+    }
+
+    ProcessedMeme {
+        animated: false,
+        blurhash,
+        content_type: "image/webp",
+        extension: "webp",
+        media_type: "image",
+        phash: Some(phash),
+        poster: Vec::new(),
+        quality: Some(quality),
+        renditions,
+    }
+}
+
+/// Format and size of an already stored image, read from its header
+pub struct Probe {
+    pub animated: bool,
+    pub content_type: &'static str,
+    pub height: u32,
+    pub width: u32,
+}
+
+pub fn probe(data: &[u8]) -> Result<Probe, String> {
+    let reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?;
+
+    let format = reader.format().ok_or("Unknown image format")?;
+
+    let animated = match format {
+        ImageFormat::Gif => {
+            let decoder = GifDecoder::new(Cursor::new(data)).map_err(|e| e.to_string())?;
+            decoder.into_frames().take(2).count() > 1
+        }
+        ImageFormat::WebP => WebPDecoder::new(Cursor::new(data))
+            .map_err(|e| e.to_string())?
+            .has_animation(),
+        _ => false,
+    };
+
+    let (width, height) = reader.into_dimensions().map_err(|e| e.to_string())?;
+
+    Ok(Probe {
+        animated,
+        content_type: format.to_mime_type(),
+        height,
+        width,
+    })
+}
+
+/// 16 bit RGBA is the widest pixel any decoder produces
+const MAX_BYTES_PER_PIXEL: u64 = 8;
+
+/// Only caps allocations, width and height are left to `check_dimensions` so images over them
+/// get its 422 rather than a decoder error
+fn limits(config: &Config) -> Limits {
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(config.image_max_pixels.saturating_mul(MAX_BYTES_PER_PIXEL));
+    limits
+}
+
+/// Checks the dimensions read from the header before any pixel is decoded. The pixel budget
+/// counts every frame of animated images, `frames` is how many of them were decoded so far
+fn check_dimensions(
+    config: &Config,
+    width: u32,
+    height: u32,
+    frames: usize,
+) -> Result<(), (StatusCode, String)> {
+    if frames > config.image_max_frames {
+        return Err(http_error!(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Image exceeds {} frames", config.image_max_frames)
+        ));
+    }
+
+    if width as u64 * height as u64 * frames as u64 > config.image_max_pixels {
+        return Err(http_error!(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Image exceeds {} pixels", config.image_max_pixels)
+        ));
+    }
+
+    if !config.image_downscale
+        && (width > config.image_max_width || height > config.image_max_height)
+    {
+        return Err(http_error!(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!(
+                "Image exceeds {}x{}",
+                config.image_max_width, config.image_max_height
+            )
+        ));
+    }
+
+    Ok(())
+}
+
+fn encode_webp(name: &'static str, img: &DynamicImage, quality: f32) -> Rendition {
+    let rgba = img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+
+    let data = Encoder::from_rgba(&rgba, width, height)
+        .encode(quality)
+        .to_vec();
+
+    Rendition {
+        content_type: "image/webp",
+        data,
+        extension: "webp",
+        height,
+        name,
+        width,
+    }
+}
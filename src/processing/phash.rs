@@ -0,0 +1,28 @@
+use image::{imageops::FilterType, DynamicImage};
+
+/// 64 bit difference hash, visually similar images end up a few bits apart
+pub fn dhash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+
+    hash
+}
+
+/// Hashes are split into this many bytes, hashes up to `BANDS - 1` bits apart share at least one
+pub const BANDS: u32 = 8;
+
+/// Position tagged bytes of a hash, matching the `phash_bands` column memes are searched by
+pub fn bands(hash: u64) -> Vec<i16> {
+    (0..BANDS)
+        .map(|band| (band * 256 + ((hash >> (band * 8)) & 255) as u32) as i16)
+        .collect()
+}
@@ -0,0 +1,27 @@
+use crate::models::Config;
+use crate::processing::limits;
+use image::{DynamicImage, ImageReader};
+use std::io::Cursor;
+
+/// BlurHash the client paints while the image loads. Encoding cost grows with the pixel
+/// count and the hash only keeps a few components anyway, so it is computed on a thumbnail
+pub fn blurhash(img: &DynamicImage) -> Option<String> {
+    let small = img.thumbnail(32, 32).to_rgba8();
+    let (width, height) = small.dimensions();
+
+    let (components_x, components_y) = if width >= height { (4, 3) } else { (3, 4) };
+
+    blurhash::encode(components_x, components_y, width, height, small.as_raw()).ok()
+}
+
+/// BlurHash of an already stored image, only the first frame of animated ones is decoded
+pub fn from_stored(data: &[u8], config: &Config) -> Result<String, String> {
+    let mut reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?;
+    reader.limits(limits(config));
+
+    let img = reader.decode().map_err(|e| e.to_string())?;
+
+    blurhash(&img).ok_or_else(|| "Failed to encode blurhash".to_string())
+}
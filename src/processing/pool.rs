@@ -0,0 +1,119 @@
+use crate::http_error;
+use crate::models::Config;
+use axum::http::StatusCode;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Semaphore;
+
+/// Runs image processing on Tokio's blocking threads so encodes never stall the async workers.
+/// At most `workers` jobs run at once and `queue` more may wait, anything past that gets a 503
+pub struct Pool {
+    metrics: Arc<Metrics>,
+    queue: Arc<Semaphore>,
+    queue_size: usize,
+    workers: Arc<Semaphore>,
+    workers_size: usize,
+}
+
+#[derive(Default)]
+struct Metrics {
+    failed: AtomicU64,
+    max_micros: AtomicU64,
+    processed: AtomicU64,
+    rejected: AtomicU64,
+    total_micros: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct PoolMetrics {
+    pub avg_ms: f64,
+    pub failed: u64,
+    pub in_flight: usize,
+    pub max_ms: f64,
+    pub processed: u64,
+    pub queued: usize,
+    pub rejected: u64,
+}
+
+impl Pool {
+    pub fn new(workers: usize, queue: usize) -> Self {
+        let workers = workers.max(1);
+
+        Self {
+            metrics: Arc::new(Metrics::default()),
+            queue: Arc::new(Semaphore::new(workers + queue)),
+            queue_size: workers + queue,
+            workers: Arc::new(Semaphore::new(workers)),
+            workers_size: workers,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.processing_workers, config.processing_queue)
+    }
+
+    pub async fn run<T, F>(&self, job: F) -> Result<T, (StatusCode, String)>
+    where
+        F: FnOnce() -> Result<T, (StatusCode, String)> + Send + 'static,
+        T: Send + 'static,
+    {
+        let slot = self.queue.clone().try_acquire_owned().map_err(|_| {
+            self.metrics.rejected.fetch_add(1, Ordering::Relaxed);
+            http_error!(StatusCode::SERVICE_UNAVAILABLE, "Processing queue is full")
+        })?;
+
+        let worker = self
+            .workers
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+        let metrics = self.metrics.clone();
+
+        // Permits move into the job so they are only released once it is done, even if the
+        // request that queued it gets dropped by the timeout layer
+        tokio::task::spawn_blocking(move || {
+            let _permits = (slot, worker);
+            let started = Instant::now();
+
+            let result = job();
+
+            let micros = started.elapsed().as_micros() as u64;
+            metrics.total_micros.fetch_add(micros, Ordering::Relaxed);
+            metrics.max_micros.fetch_max(micros, Ordering::Relaxed);
+            match result {
+                Ok(_) => metrics.processed.fetch_add(1, Ordering::Relaxed),
+                Err(_) => metrics.failed.fetch_add(1, Ordering::Relaxed),
+            };
+
+            result
+        })
+        .await
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?
+    }
+
+    pub fn metrics(&self) -> PoolMetrics {
+        let processed = self.metrics.processed.load(Ordering::Relaxed);
+        let failed = self.metrics.failed.load(Ordering::Relaxed);
+        let total_micros = self.metrics.total_micros.load(Ordering::Relaxed);
+
+        let in_flight = self.workers_size - self.workers.available_permits();
+        let queued = (self.queue_size - self.queue.available_permits()).saturating_sub(in_flight);
+
+        PoolMetrics {
+            avg_ms: match processed + failed {
+                0 => 0.0,
+                jobs => total_micros as f64 / jobs as f64 / 1000.0,
+            },
+            failed,
+            in_flight,
+            max_ms: self.metrics.max_micros.load(Ordering::Relaxed) as f64 / 1000.0,
+            processed,
+            queued,
+            rejected: self.metrics.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
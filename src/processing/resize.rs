@@ -0,0 +1,164 @@
+use crate::http_error;
+use crate::models::Config;
+use crate::processing::limits;
+use axum::http::StatusCode;
+use image::{
+    codecs::{avif::AvifEncoder, jpeg::JpegEncoder},
+    imageops::FilterType,
+    DynamicImage, ExtendedColorType, ImageEncoder, ImageReader,
+};
+use std::io::Cursor;
+use webp::Encoder;
+
+/// rav1e speed from 1 to 10, images are encoded while the client waits so it leans fast
+const AVIF_SPEED: u8 = 8;
+
+/// Qualities clients can ask for, any other one is rounded up to them so a meme only ever has a
+/// handful of variants per width and format
+pub const QUALITIES: [u8; 4] = [30, 50, 70, 90];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Avif,
+    Jpeg,
+    WebP,
+}
+
+impl Format {
+    /// Picks the smallest format the `Accept` header allows, every client can show JPEG
+    pub fn negotiate(accept: &str) -> Self {
+        let accepts = |content_type: &str| {
+            accept
+                .split(',')
+                .filter_map(|media_range| media_range.split(';').next())
+                .any(|media_range| media_range.trim() == content_type)
+        };
+
+        if accepts("image/avif") {
+            Self::Avif
+        } else if accepts("image/webp") {
+            Self::WebP
+        } else {
+            Self::Jpeg
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Avif => "image/avif",
+            Self::Jpeg => "image/jpeg",
+            Self::WebP => "image/webp",
+        }
+    }
+
+    pub fn default_quality(self, config: &Config) -> u8 {
+        let quality = match self {
+            Self::Avif => config.compression_quality_avif,
+            Self::Jpeg => config.compression_quality_jpeg,
+            Self::WebP => config.compression_quality,
+        };
+
+        quality as u8
+    }
+
+    /// The requested quality rounded up to one of `QUALITIES`, or the configured one of the format
+    pub fn quality(self, requested: Option<u8>, config: &Config) -> u8 {
+        match requested {
+            Some(requested) => QUALITIES
+                .into_iter()
+                .find(|&quality| quality >= requested)
+                .unwrap_or(QUALITIES[QUALITIES.len() - 1]),
+            None => self.default_quality(config),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Avif => "avif",
+            Self::Jpeg => "jpg",
+            Self::WebP => "webp",
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "avif" => Ok(Self::Avif),
+            "jpeg" | "jpg" => Ok(Self::Jpeg),
+            "webp" => Ok(Self::WebP),
+            _ => Err(format!("Invalid format: {}", s)),
+        }
+    }
+}
+
+/// Re-encodes a stored image, shrunk to `width` when it is wider. Stored images are already
+/// oriented and stripped, so only the first frame of animated ones is kept
+pub fn resize(
+    data: &[u8],
+    width: Option<u32>,
+    format: Format,
+    quality: u8,
+    config: &Config,
+) -> Result<Vec<u8>, (StatusCode, String)> {
+    let mut reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+    reader.limits(limits(config));
+
+    let mut img = reader
+        .decode()
+        .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+
+    if let Some(width) = width {
+        if width < img.width() {
+            img = img.resize(width, u32::MAX, FilterType::Lanczos3);
+        }
+    }
+
+    encode(&img, format, quality)
+}
+
+pub fn encode(
+    img: &DynamicImage,
+    format: Format,
+    quality: u8,
+) -> Result<Vec<u8>, (StatusCode, String)> {
+    let mut data = Vec::new();
+
+    match format {
+        Format::Avif => {
+            let rgba = img.to_rgba8();
+            AvifEncoder::new_with_speed_quality(&mut data, AVIF_SPEED, quality)
+                .write_image(
+                    rgba.as_raw(),
+                    rgba.width(),
+                    rgba.height(),
+                    ExtendedColorType::Rgba8,
+                )
+                .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+        }
+        // JPEG has no alpha channel
+        Format::Jpeg => {
+            let rgb = img.to_rgb8();
+            JpegEncoder::new_with_quality(&mut data, quality)
+                .write_image(
+                    rgb.as_raw(),
+                    rgb.width(),
+                    rgb.height(),
+                    ExtendedColorType::Rgb8,
+                )
+                .map_err(|e| http_error!(StatusCode::INTERNAL_SERVER_ERROR, err: e))?;
+        }
+        Format::WebP => {
+            let rgba = img.to_rgba8();
+            data = Encoder::from_rgba(&rgba, rgba.width(), rgba.height())
+                .encode(quality as f32)
+                .to_vec();
+        }
+    }
+
+    Ok(data)
+}
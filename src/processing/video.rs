@@ -0,0 +1,672 @@
+use crate::http_error;
+use crate::models::Config;
+use crate::processing::{
+    decode, limits, process_still, watermark::Watermark, ProcessedMeme, Rendition,
+};
+use axum::http::StatusCode;
+use image::{DynamicImage, ImageFormat, ImageReader};
+use matroska_demuxer::{Frame, MatroskaFile, TrackType};
+use mp4::Mp4Reader;
+use std::io::Cursor;
+use std::time::Duration;
+
+/// Major brands of plain MP4s, QuickTime, 3GP and HEIF/AVIF share the box format but not these
+const MP4_BRANDS: [&[u8; 4]; 11] = [
+    b"avc1", b"dash", b"iso2", b"iso3", b"iso4", b"iso5", b"iso6", b"isom", b"M4V ", b"mp41",
+    b"mp42",
+];
+/// Compatible brands that give away another format behind an MP4 major brand
+const NON_MP4_BRANDS: [&[u8; 4]; 9] = [
+    b"3g2a", b"3gp4", b"3gp5", b"3gp6", b"avif", b"heic", b"heix", b"mif1", b"qt  ",
+];
+/// Boxes that only carry metadata: user data (GPS, camera model), iTunes style tags and vendor
+/// extensions such as XMP
+const MP4_METADATA_BOXES: [&[u8; 4]; 3] = [b"meta", b"udta", b"uuid"];
+const MP4_CONTAINER_BOXES: [&[u8; 4]; 7] = [
+    b"mdia", b"minf", b"moof", b"moov", b"mvex", b"traf", b"trak",
+];
+/// Boxes starting with a creation and a modification time
+const MP4_TIMESTAMPED_BOXES: [&[u8; 4]; 3] = [b"mdhd", b"mvhd", b"tkhd"];
+
+const WEBM_ATTACHMENTS: u32 = 0x1941A469;
+const WEBM_CLUSTER: u32 = 0x1F43B675;
+const WEBM_DATE_UTC: u32 = 0x4461;
+const WEBM_INFO: u32 = 0x1549A966;
+const WEBM_SEGMENT: u32 = 0x18538067;
+const WEBM_TAGS: u32 = 0x1254C367;
+const WEBM_TITLE: u32 = 0x7BA9;
+const WEBM_VOID: u8 = 0xEC;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Container {
+    Mp4,
+    WebM,
+}
+
+struct Video {
+    duration: Duration,
+    /// Only set when the first frame could be decoded without a video decoder
+    first_frame: Option<DynamicImage>,
+    height: u32,
+    width: u32,
+}
+
+/// Recognizes videos by their magic bytes, whatever content type the client sent is ignored
+pub fn sniff(data: &[u8]) -> Option<Container> {
+    if data.get(4..8) == Some(b"ftyp") {
+        let ftyp_size = u32::from_be_bytes(data.get(0..4)?.try_into().ok()?) as usize;
+        let major_brand = data.get(8..12)?;
+        // Compatible brands follow the major brand and its minor version
+        let compatible_brands = data.get(16..ftyp_size.min(data.len()))?.chunks_exact(4);
+
+        let is_mp4 = MP4_BRANDS.iter().any(|brand| major_brand == *brand)
+            && !compatible_brands
+                .into_iter()
+                .any(|brand| NON_MP4_BRANDS.iter().any(|other| brand == *other));
+
+        is_mp4.then_some(Container::Mp4)
+    } else if data.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        Some(Container::WebM)
+    } else {
+        None
+    }
+}
+
+/// Videos are stored as uploaded apart from their metadata, which is blanked out in place. Only
+/// the poster frame goes through the image pipeline and gets the watermark. `poster` is an image
+/// picked by the client, used when the first frame cannot be decoded here
+pub fn process(
+    file_data: &[u8],
+    container: Container,
+    poster: Option<&[u8]>,
+    config: &Config,
+    watermark: Option<&Watermark>,
+) -> Result<ProcessedMeme, (StatusCode, String)> {
+    if file_data.len() > config.video_max_size {
+        return Err(http_error!(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    let max_duration = Duration::from_secs(config.video_max_duration);
+
+    let mut file_data = file_data.to_vec();
+    let len = file_data.len();
+    let stripped = match container {
+        Container::Mp4 => strip_mp4_metadata(&mut file_data, 0, len),
+        Container::WebM => strip_webm_metadata(&mut file_data),
+    };
+    if stripped.is_none() {
+        return Err(http_error!(StatusCode::BAD_REQUEST, "Invalid video format"));
+    }
+
+    let (video, content_type, extension) = match container {
+        Container::Mp4 => (read_mp4(&file_data)?, "video/mp4", "mp4"),
+        Container::WebM => (
+            read_webm(&file_data, max_duration, config)?,
+            "video/webm",
+            "webm",
+        ),
+    };
+
+    if video.duration > max_duration {
+        return Err(http_error!(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Video exceeds {} seconds", config.video_max_duration)
+        ));
+    }
+
+    if video.width > config.image_max_width || video.height > config.image_max_height {
+        return Err(http_error!(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!(
+                "Video exceeds {}x{}",
+                config.image_max_width, config.image_max_height
+            )
+        ));
+    }
+
+    let poster = match (video.first_frame, poster) {
+        (Some(img), _) => Some(img),
+        (None, Some(data)) => {
+            let reader = ImageReader::new(Cursor::new(data))
+                .with_guessed_format()
+                .map_err(|_| http_error!(StatusCode::BAD_REQUEST, "Invalid poster format"))?;
+            Some(decode(reader, config)?)
+        }
+        (None, None) => None,
+    }
+    .map(|img| process_still(img, config, watermark));
+
+    let (blurhash, phash, quality, poster) = match poster {
+        Some(poster) => {
+            // Named apart from the renditions of images, so clients asking for a video's
+            // `thumbnail` don't get a still
+            let mut renditions = poster.renditions;
+            for rendition in renditions.iter_mut() {
+                rendition.name = match rendition.name {
+                    "thumbnail" => "poster_thumbnail",
+                    "feed" => "poster_feed",
+                    _ => "poster",
+                };
+            }
+            (poster.blurhash, poster.phash, poster.quality, renditions)
+        }
+        None => (None, None, None, Vec::new()),
+    };
+
+    Ok(ProcessedMeme {
+        animated: true,
+        blurhash,
+        content_type,
+        extension,
+        media_type: "video",
+        phash,
+        poster,
+        quality,
+        renditions: vec![Rendition {
+            content_type,
+            data: file_data,
+            extension,
+            height: video.height,
+            name: "full",
+            width: video.width,
+        }],
+    })
+}
+
+/// Turns metadata boxes into `free` boxes of the same size and zeroes creation times, so every
+/// chunk offset stays valid without remuxing. Returns `None` if the boxes can't be walked
+fn strip_mp4_metadata(data: &mut [u8], start: usize, end: usize) -> Option<()> {
+    let mut pos = start;
+
+    while pos < end {
+        let size = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let kind: [u8; 4] = data.get(pos + 4..pos + 8)?.try_into().ok()?;
+
+        // A size of 1 means a 64 bit size follows the type, 0 that the box runs to the end
+        let (header, size) = match size {
+            0 => (8, end - pos),
+            1 => (
+                16,
+                usize::try_from(u64::from_be_bytes(
+                    data.get(pos + 8..pos + 16)?.try_into().ok()?,
+                ))
+                .ok()?,
+            ),
+            size => (8, size),
+        };
+        if size < header || size > end - pos {
+            return None;
+        }
+        let body = pos + header..pos + size;
+
+        if MP4_METADATA_BOXES.contains(&&kind) {
+            data[pos + 4..pos + 8].copy_from_slice(b"free");
+            data[body].fill(0);
+        } else if MP4_CONTAINER_BOXES.contains(&&kind) {
+            strip_mp4_metadata(data, body.start, body.end)?;
+        } else if MP4_TIMESTAMPED_BOXES.contains(&&kind) {
+            // Version 1 boxes have 64 bit times, version 0 ones 32 bit times
+            let times = match data[body.clone()].first()? {
+                1 => 16,
+                _ => 8,
+            };
+            data[body].get_mut(4..4 + times)?.fill(0);
+        }
+
+        pos += size;
+    }
+
+    Some(())
+}
+
+/// Turns tags, attachments, the title and the date of WebMs into Void elements of the same size.
+/// Returns `None` if the elements can't be walked
+fn strip_webm_metadata(data: &mut [u8]) -> Option<()> {
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let (id, id_len) = read_ebml_id(data, pos)?;
+        let (size, size_len) = read_ebml_size(data, pos + id_len)?;
+        let body = pos + id_len + size_len;
+
+        match size {
+            // Children of the elements holding metadata are walked as if they were siblings,
+            // so are those of elements streamed with an unknown size, such as live clusters
+            Some(_) if matches!(id, WEBM_SEGMENT | WEBM_INFO) => pos = body,
+            None if id != WEBM_CLUSTER && id != WEBM_SEGMENT => return None,
+            None => pos = body,
+            Some(size) => {
+                let end = body.checked_add(usize::try_from(size).ok()?)?;
+                if end > data.len() {
+                    return None;
+                }
+
+                if matches!(
+                    id,
+                    WEBM_ATTACHMENTS | WEBM_DATE_UTC | WEBM_TAGS | WEBM_TITLE
+                ) {
+                    write_ebml_void(&mut data[pos..end]);
+                }
+                pos = end;
+            }
+        }
+    }
+
+    Some(())
+}
+
+/// Element IDs keep their length marker, e.g. 0x1A45DFA3 for the EBML header
+fn read_ebml_id(data: &[u8], pos: usize) -> Option<(u32, usize)> {
+    let len = data.get(pos)?.leading_zeros() as usize + 1;
+    if len > 4 {
+        return None;
+    }
+
+    let id = data
+        .get(pos..pos + len)?
+        .iter()
+        .fold(0, |id, byte| id << 8 | *byte as u32);
+    Some((id, len))
+}
+
+/// `None` for the all ones value, which marks an unknown size
+fn read_ebml_size(data: &[u8], pos: usize) -> Option<(Option<u64>, usize)> {
+    let len = data.get(pos)?.leading_zeros() as usize + 1;
+    if len > 8 {
+        return None;
+    }
+
+    let value = data
+        .get(pos..pos + len)?
+        .iter()
+        .fold(0, |value, byte| value << 8 | *byte as u64)
+        & (u64::MAX >> (64 - 7 * len));
+    let unknown = u64::MAX >> (64 - 7 * len);
+
+    Some(((value != unknown).then_some(value), len))
+}
+
+/// Overwrites a whole element, header included, with a zero filled Void element
+fn write_ebml_void(element: &mut [u8]) {
+    // Every element is at least an ID and a size byte long
+    let size_len = (element.len() - 1).min(8);
+    let size = (element.len() - 1 - size_len) as u64;
+
+    element[0] = WEBM_VOID;
+    element[1..1 + size_len].copy_from_slice(&size.to_be_bytes()[8 - size_len..]);
+    element[1] |= 1 << (8 - size_len);
+    element[1 + size_len..].fill(0);
+}
+
+fn read_mp4(data: &[u8]) -> Result<Video, (StatusCode, String)> {
+    let mp4 = Mp4Reader::read_header(Cursor::new(data), data.len() as u64)
+        .map_err(|_| http_error!(StatusCode::BAD_REQUEST, "Invalid video format"))?;
+
+    let track = mp4
+        .tracks()
+        .values()
+        .find(|track| matches!(track.track_type(), Ok(mp4::TrackType::Video)))
+        .ok_or_else(|| http_error!(StatusCode::BAD_REQUEST, "Invalid video format"))?;
+
+    // Fragmented files leave the movie duration at zero, their tracks still have one
+    let duration = mp4
+        .tracks()
+        .values()
+        .map(|track| track.duration())
+        .fold(mp4.duration(), Duration::max);
+
+    Ok(Video {
+        duration,
+        first_frame: None,
+        height: track.height() as u32,
+        width: track.width() as u32,
+    })
+}
+
+fn read_webm(
+    data: &[u8],
+    max_duration: Duration,
+    config: &Config,
+) -> Result<Video, (StatusCode, String)> {
+    let mut webm = MatroskaFile::open(Cursor::new(data))
+        .map_err(|_| http_error!(StatusCode::BAD_REQUEST, "Invalid video format"))?;
+
+    if webm.ebml_header().doc_type() != "webm" {
+        return Err(http_error!(StatusCode::BAD_REQUEST, "Invalid video format"));
+    }
+
+    let track = webm
+        .tracks()
+        .iter()
+        .find(|track| track.track_type() == TrackType::Video)
+        .ok_or_else(|| http_error!(StatusCode::BAD_REQUEST, "Invalid video format"))?;
+
+    let video = track
+        .video()
+        .ok_or_else(|| http_error!(StatusCode::BAD_REQUEST, "Invalid video format"))?;
+
+    let track_number = track.track_number().get();
+    let is_vp8 = track.codec_id() == "V_VP8";
+    let width = video.pixel_width().get() as u32;
+    let height = video.pixel_height().get() as u32;
+
+    let timestamp_scale = webm.info().timestamp_scale().get();
+    let header_duration = webm
+        .info()
+        .duration()
+        .map(|duration| Duration::from_nanos((duration * timestamp_scale as f64) as u64));
+
+    // MediaRecorder writes WebM without a duration, then the last frame's timestamp stands in
+    let mut duration = header_duration.unwrap_or_default();
+    let mut first_frame = None;
+    let mut frame = Frame::default();
+    let mut seen_first_frame = false;
+
+    while webm
+        .next_frame(&mut frame)
+        .map_err(|_| http_error!(StatusCode::BAD_REQUEST, "Invalid video format"))?
+    {
+        duration = duration.max(Duration::from_nanos(
+            frame.timestamp.saturating_mul(timestamp_scale),
+        ));
+
+        if frame.track == track_number && !seen_first_frame {
+            seen_first_frame = true;
+            if is_vp8 {
+                first_frame = decode_vp8_keyframe(&frame.data, config);
+            }
+        }
+
+        if duration > max_duration || (seen_first_frame && header_duration.is_some()) {
+            break;
+        }
+    }
+
+    Ok(Video {
+        duration,
+        first_frame,
+        height,
+        width,
+    })
+}
+
+/// A VP8 keyframe is exactly what a lossy WebP holds, so once wrapped in a RIFF header the
+/// WebP decoder turns it into a poster without needing a video decoder
+fn decode_vp8_keyframe(frame: &[u8], config: &Config) -> Option<DynamicImage> {
+    // The lowest bit of the frame tag is unset on keyframes
+    if frame.first()? & 1 != 0 {
+        return None;
+    }
+
+    let chunk_size = u32::try_from(frame.len()).ok()?;
+    let padding = chunk_size % 2;
+
+    let mut webp = Vec::with_capacity(20 + frame.len() + padding as usize);
+    webp.extend_from_slice(b"RIFF");
+    webp.extend_from_slice(&(12 + chunk_size + padding).to_le_bytes());
+    webp.extend_from_slice(b"WEBP");
+    webp.extend_from_slice(b"VP8 ");
+    webp.extend_from_slice(&chunk_size.to_le_bytes());
+    webp.extend_from_slice(frame);
+    webp.resize(webp.len() + padding as usize, 0);
+
+    let mut reader = ImageReader::with_format(Cursor::new(webp), ImageFormat::WebP);
+    reader.limits(limits(config));
+    reader.decode().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        [
+            &(8 + body.len() as u32).to_be_bytes(),
+            kind.as_slice(),
+            body,
+        ]
+        .concat()
+    }
+
+    fn ftyp(major_brand: &[u8; 4], compatible_brands: &[&[u8; 4]]) -> Vec<u8> {
+        let mut body = [major_brand.as_slice(), &[0, 0, 2, 0]].concat();
+        for brand in compatible_brands {
+            body.extend_from_slice(*brand);
+        }
+        mp4_box(b"ftyp", &body)
+    }
+
+    /// Version 0 boxes carry 32 bit times, version 1 ones 64 bit times, both followed by a
+    /// timescale that has to survive
+    fn timestamped(kind: &[u8; 4], version: u8) -> Vec<u8> {
+        let times = if version == 1 { 16 } else { 8 };
+        let body = [
+            [version, 0, 0, 0].as_slice(),
+            &vec![0xAB; times],
+            &1000_u32.to_be_bytes(),
+        ]
+        .concat();
+        mp4_box(kind, &body)
+    }
+
+    fn mp4_with_metadata() -> Vec<u8> {
+        let mdia = mp4_box(b"mdia", &timestamped(b"mdhd", 0));
+        let trak = mp4_box(b"trak", &[timestamped(b"tkhd", 1), mdia].concat());
+        let udta = mp4_box(b"udta", b"\xA9xyz+48.8584+002.2945/");
+        let moov = mp4_box(b"moov", &[timestamped(b"mvhd", 0), trak, udta].concat());
+
+        [
+            ftyp(b"isom", &[b"isom", b"mp41"]),
+            moov,
+            mp4_box(b"meta", b"\0\0\0\0Canon EOS"),
+            mp4_box(b"uuid", b"<x:xmpmeta/>"),
+            mp4_box(b"mdat", b"FRAMES"),
+        ]
+        .concat()
+    }
+
+    fn contains(data: &[u8], needle: &[u8]) -> bool {
+        data.windows(needle.len()).any(|window| window == needle)
+    }
+
+    #[test]
+    fn sniff_takes_plain_mp4s_and_webms_only() {
+        assert!(sniff(&ftyp(b"isom", &[b"isom", b"avc1"])) == Some(Container::Mp4));
+        assert!(sniff(&ftyp(b"mp42", &[b"mp42", b"mp41"])) == Some(Container::Mp4));
+        assert!(sniff(&[0x1A, 0x45, 0xDF, 0xA3, 0x9F]) == Some(Container::WebM));
+
+        // QuickTime, 3GP and HEIF/AVIF by major brand or behind an MP4 one
+        assert!(sniff(&ftyp(b"qt  ", &[b"qt  "])).is_none());
+        assert!(sniff(&ftyp(b"3gp4", &[b"isom", b"3gp4"])).is_none());
+        assert!(sniff(&ftyp(b"heic", &[b"mif1", b"heic"])).is_none());
+        assert!(sniff(&ftyp(b"isom", &[b"isom", b"3gp6"])).is_none());
+        assert!(sniff(&ftyp(b"mp42", &[b"mif1", b"avif"])).is_none());
+        assert!(sniff(&ftyp(b"isom", &[b"isom", b"qt  "])).is_none());
+
+        assert!(sniff(b"GIF89a").is_none());
+    }
+
+    #[test]
+    fn sniff_survives_truncated_and_lying_ftyps() {
+        let data = ftyp(b"isom", &[b"isom", b"heic"]);
+        for len in 0..data.len() {
+            let _ = sniff(&data[..len]);
+        }
+
+        let mut oversized = ftyp(b"isom", &[b"isom"]);
+        oversized[..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(sniff(&oversized) == Some(Container::Mp4));
+
+        let mut undersized = ftyp(b"isom", &[b"isom"]);
+        undersized[..4].copy_from_slice(&4_u32.to_be_bytes());
+        assert!(sniff(&undersized).is_none());
+    }
+
+    #[test]
+    fn strip_mp4_metadata_blanks_metadata_boxes_and_times() {
+        let original = mp4_with_metadata();
+        let mut data = original.clone();
+        let len = data.len();
+
+        assert!(strip_mp4_metadata(&mut data, 0, len).is_some());
+        assert_eq!(data.len(), original.len());
+
+        for kind in [b"udta", b"meta", b"uuid"] {
+            assert!(contains(&original, kind));
+            assert!(!contains(&data, kind), "{}", String::from_utf8_lossy(kind));
+        }
+        assert!(!contains(&data, b"+48.8584"));
+        assert!(!contains(&data, b"Canon"));
+        assert!(!contains(&data, b"xmpmeta"));
+        assert!(!contains(&data, &[0xAB]));
+
+        // Every box keeps its size and place, only kinds and contents changed
+        assert!(contains(&data, b"mdatFRAMES"));
+        assert_eq!(
+            data.windows(4).filter(|window| window == b"free").count(),
+            3
+        );
+        assert_eq!(
+            data.windows(4)
+                .filter(|window| *window == 1000_u32.to_be_bytes())
+                .count(),
+            3
+        );
+
+        let mut again = data.clone();
+        assert!(strip_mp4_metadata(&mut again, 0, len).is_some());
+        assert_eq!(again, data);
+    }
+
+    #[test]
+    fn strip_mp4_metadata_rejects_broken_box_sizes() {
+        let original = mp4_with_metadata();
+        for len in 0..original.len() {
+            let mut data = original[..len].to_vec();
+            let _ = strip_mp4_metadata(&mut data, 0, len);
+        }
+
+        let oversized = [mp4_box(b"mdat", b"FRAMES"), mp4_box(b"udta", b"GPS")].concat();
+        for size in [u32::MAX, 1000, 12] {
+            let mut data = oversized.clone();
+            data[14..18].copy_from_slice(&size.to_be_bytes());
+            let len = data.len();
+            assert!(strip_mp4_metadata(&mut data, 0, len).is_none(), "{}", size);
+        }
+
+        // Sizes below the header, 64 bit sizes past the end and times past their box
+        for data in [
+            [&4_u32.to_be_bytes(), b"udta".as_slice()].concat(),
+            [
+                &1_u32.to_be_bytes(),
+                b"udta".as_slice(),
+                &u64::MAX.to_be_bytes(),
+            ]
+            .concat(),
+            [
+                mp4_box(b"mvhd", &[0, 0, 0, 0, 1]),
+                mp4_box(b"mdat", b"FRAMES"),
+            ]
+            .concat(),
+            mp4_box(b"moov", &mp4_box(b"mvhd", &[])),
+        ] {
+            let mut data = data;
+            let len = data.len();
+            let before = data.clone();
+            assert!(strip_mp4_metadata(&mut data, 0, len).is_none());
+            assert_eq!(&data[data.len() - 6..], &before[before.len() - 6..]);
+        }
+    }
+
+    fn ebml(id: &[u8], body: &[u8]) -> Vec<u8> {
+        // Eight byte sizes, as muxers that write the size after the body tend to
+        let mut size = (body.len() as u64).to_be_bytes();
+        size[0] = 0x01;
+        [id, &size, body].concat()
+    }
+
+    fn webm_with_metadata() -> Vec<u8> {
+        let header = ebml(&[0x1A, 0x45, 0xDF, 0xA3], &ebml(&[0x42, 0x82], b"webm"));
+        let info = ebml(
+            &[0x15, 0x49, 0xA9, 0x66],
+            &[
+                ebml(&[0x2A, 0xD7, 0xB1], &[0x0F, 0x42, 0x40]),
+                ebml(&[0x7B, 0xA9], b"holiday in Paris"),
+                ebml(&[0x44, 0x61], &0x1122334455667788_u64.to_be_bytes()),
+            ]
+            .concat(),
+        );
+        let tags = ebml(&[0x12, 0x54, 0xC3, 0x67], b"ENCODER=phone");
+        let attachments = ebml(&[0x19, 0x41, 0xA4, 0x69], b"cover.jpg");
+        let cluster = ebml(&[0x1F, 0x43, 0xB6, 0x75], b"FRAMES");
+
+        // Segments streamed by MediaRecorder have an unknown size
+        let segment = [
+            [
+                0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            ]
+            .as_slice(),
+            &info,
+            &tags,
+            &attachments,
+            &cluster,
+        ]
+        .concat();
+
+        [header, segment].concat()
+    }
+
+    #[test]
+    fn strip_webm_metadata_voids_tags_attachments_title_and_date() {
+        let original = webm_with_metadata();
+        let mut data = original.clone();
+
+        assert!(strip_webm_metadata(&mut data).is_some());
+        assert_eq!(data.len(), original.len());
+
+        for gone in [
+            b"holiday".as_slice(),
+            &0x1122334455667788_u64.to_be_bytes(),
+            &[0x12, 0x54, 0xC3, 0x67],
+            b"ENCODER",
+            &[0x19, 0x41, 0xA4, 0x69],
+            b"cover.jpg",
+        ] {
+            assert!(contains(&original, gone));
+            assert!(!contains(&data, gone), "{:?}", gone);
+        }
+
+        assert!(contains(&data, b"webm"));
+        assert!(contains(&data, &[0x2A, 0xD7, 0xB1]));
+        assert!(contains(&data, b"FRAMES"));
+
+        // The voids are elements of their own, the file still walks the same way
+        let mut again = data.clone();
+        assert!(strip_webm_metadata(&mut again).is_some());
+        assert_eq!(again, data);
+    }
+
+    #[test]
+    fn strip_webm_metadata_rejects_broken_element_sizes() {
+        let original = webm_with_metadata();
+        for len in 0..original.len() {
+            let _ = strip_webm_metadata(&mut original[..len].to_vec());
+        }
+
+        for mut data in [
+            // A tag running past the end
+            [
+                0x12, 0x54, 0xC3, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE,
+            ]
+            .to_vec(),
+            [0x12, 0x54, 0xC3, 0x67, 0x90, 0x00].to_vec(),
+            // IDs and sizes too long to be valid
+            [0x00, 0x81, 0x00].to_vec(),
+            [0x42, 0x82, 0x00, 0x00].to_vec(),
+            // Only segments and clusters may have an unknown size
+            [0x12, 0x54, 0xC3, 0x67, 0xFF].to_vec(),
+        ] {
+            assert!(strip_webm_metadata(&mut data).is_none(), "{:?}", data);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::models::{Config, WatermarkPosition};
+use image::{imageops, imageops::FilterType, Rgba, RgbaImage};
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+pub struct Watermark {
+    mark: RgbaImage,
+    opacity: f32,
+    position: WatermarkPosition,
+    scale: f32,
+}
+
+impl Watermark {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let mark = match &config.watermark_logo_path {
+            Some(path) => image::open(path)
+                .map_err(|e| format!("Failed to open watermark logo {}: {}", path, e))?
+                .to_rgba8(),
+            None => render_text(&config.watermark_text),
+        };
+
+        Ok(Self {
+            mark,
+            opacity: config.watermark_opacity,
+            position: config.watermark_position,
+            scale: config.watermark_scale,
+        })
+    }
+
+    pub fn apply(&self, img: &mut RgbaImage) {
+        let (mark, x, y) = self.render(img.width(), img.height());
+        imageops::overlay(img, &mark, x, y);
+    }
+
+    /// Scales the mark for an image of the given size, returning it with the offset to draw it at
+    pub fn render(&self, width: u32, height: u32) -> (RgbaImage, i64, i64) {
+        let mark_width = ((width as f32 * self.scale).round() as u32).clamp(1, width);
+        let mark_height = ((self.mark.height() as f32 * mark_width as f32
+            / self.mark.width() as f32)
+            .round() as u32)
+            .clamp(1, height);
+
+        // Nearest keeps the bitmap font crisp when it's blown up
+        let filter = if mark_width > self.mark.width() {
+            FilterType::Nearest
+        } else {
+            FilterType::Triangle
+        };
+
+        let mut mark = imageops::resize(&self.mark, mark_width, mark_height, filter);
+        for pixel in mark.pixels_mut() {
+            pixel[3] = (pixel[3] as f32 * self.opacity).round() as u8;
+        }
+
+        let margin = (width.min(height) / 50) as i64;
+        let right = width as i64 - mark_width as i64 - margin;
+        let bottom = height as i64 - mark_height as i64 - margin;
+
+        let (x, y) = match self.position {
+            WatermarkPosition::BottomLeft => (margin, bottom),
+            WatermarkPosition::BottomRight => (right, bottom),
+            WatermarkPosition::Center => (
+                (width as i64 - mark_width as i64) / 2,
+                (height as i64 - mark_height as i64) / 2,
+            ),
+            WatermarkPosition::TopLeft => (margin, margin),
+            WatermarkPosition::TopRight => (right, margin),
+        };
+
+        (mark, x, y)
+    }
+}
+
+/// Draws the text in white with a dark outline so it reads on any background
+fn render_text(text: &str) -> RgbaImage {
+    let chars: Vec<char> = text.chars().collect();
+    let width = (chars.len() as u32 * (GLYPH_WIDTH + 1)).max(1) + 1;
+    let height = GLYPH_HEIGHT + 2;
+
+    let mut mark = RgbaImage::new(width, height);
+
+    let lit_pixels: Vec<(u32, u32)> = chars
+        .iter()
+        .enumerate()
+        .flat_map(|(i, &c)| {
+            let rows = glyph(c);
+            (0..GLYPH_HEIGHT).flat_map(move |row| {
+                (0..GLYPH_WIDTH)
+                    .filter(move |col| rows[row as usize] & (1 << (GLYPH_WIDTH - 1 - col)) != 0)
+                    .map(move |col| (i as u32 * (GLYPH_WIDTH + 1) + col + 1, row + 1))
+            })
+        })
+        .collect();
+
+    for &(x, y) in &lit_pixels {
+        for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+            let (nx, ny) = ((x as i64 + dx) as u32, (y as i64 + dy) as u32);
+            mark.put_pixel(nx, ny, Rgba([0, 0, 0, 160]));
+        }
+    }
+
+    for &(x, y) in &lit_pixels {
+        mark.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+    }
+
+    mark
+}
+
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT as usize] {
+    match c.to_ascii_lowercase() {
+        'a' => [0x00, 0x00, 0x0E, 0x01, 0x0F, 0x11, 0x0F],
+        'b' => [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x1E],
+        'c' => [0x00, 0x00, 0x0E, 0x10, 0x10, 0x11, 0x0E],
+        'd' => [0x01, 0x01, 0x0D, 0x13, 0x11, 0x11, 0x0F],
+        'e' => [0x00, 0x00, 0x0E, 0x11, 0x1F, 0x10, 0x0E],
+        'f' => [0x06, 0x09, 0x08, 0x1C, 0x08, 0x08, 0x08],
+        'g' => [0x00, 0x0F, 0x11, 0x11, 0x0F, 0x01, 0x0E],
+        'h' => [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x11],
+        'i' => [0x04, 0x00, 0x0C, 0x04, 0x04, 0x04, 0x0E],
+        'j' => [0x02, 0x00, 0x06, 0x02, 0x02, 0x12, 0x0C],
+        'k' => [0x10, 0x10, 0x12, 0x14, 0x18, 0x14, 0x12],
+        'l' => [0x0C, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'm' => [0x00, 0x00, 0x1A, 0x15, 0x15, 0x11, 0x11],
+        'n' => [0x00, 0x00, 0x16, 0x19, 0x11, 0x11, 0x11],
+        'o' => [0x00, 0x00, 0x0E, 0x11, 0x11, 0x11, 0x0E],
+        'p' => [0x00, 0x00, 0x1E, 0x11, 0x1E, 0x10, 0x10],
+        'q' => [0x00, 0x00, 0x0D, 0x13, 0x0F, 0x01, 0x01],
+        'r' => [0x00, 0x00, 0x16, 0x19, 0x10, 0x10, 0x10],
+        's' => [0x00, 0x00, 0x0E, 0x10, 0x0E, 0x01, 0x1E],
+        't' => [0x08, 0x08, 0x1C, 0x08, 0x08, 0x09, 0x06],
+        'u' => [0x00, 0x00, 0x11, 0x11, 0x11, 0x13, 0x0D],
+        'v' => [0x00, 0x00, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'w' => [0x00, 0x00, 0x11, 0x11, 0x15, 0x15, 0x0A],
+        'x' => [0x00, 0x00, 0x11, 0x0A, 0x04, 0x0A, 0x11],
+        'y' => [0x00, 0x00, 0x11, 0x11, 0x0F, 0x01, 0x0E],
+        'z' => [0x00, 0x00, 0x1F, 0x02, 0x04, 0x08, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '@' => [0x0E, 0x11, 0x17, 0x15, 0x17, 0x10, 0x0E],
+        _ => [0x00; GLYPH_HEIGHT as usize],
+    }
+}
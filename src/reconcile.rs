@@ -0,0 +1,99 @@
+use crate::storage::{self, Storage};
+use chrono::{Duration, Utc};
+use sqlx::postgres::PgPool;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+pub struct Report {
+    pub deleted: Vec<String>,
+    /// Object keys referenced by the database with no object behind them
+    pub missing: Vec<String>,
+    /// Objects older than the grace period that no meme points to, including cached variants
+    /// of deleted memes
+    pub orphaned: Vec<String>,
+}
+
+/// Compares every object in storage with every object key in the database. Uploads write the
+/// object before the row, so objects younger than `grace_period` are never reported
+pub async fn run(
+    db: &PgPool,
+    storage: &dyn Storage,
+    grace_period: Duration,
+    delete: bool,
+) -> Result<Report, String> {
+    let referenced: HashSet<String> = sqlx::query_as::<_, (String,)>(
+        "
+        SELECT object_key FROM memes
+        UNION
+        SELECT object_key FROM meme_renditions
+        ",
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| e.to_string())?
+    .into_iter()
+    .map(|(object_key,)| object_key)
+    .collect();
+
+    let objects = storage.list().await?;
+
+    let stored: HashSet<String> = objects.iter().map(|object| object.key.clone()).collect();
+
+    let mut missing: Vec<String> = referenced.difference(&stored).cloned().collect();
+    missing.sort();
+
+    let cutoff = Utc::now() - grace_period;
+    let mut orphaned: Vec<String> = objects
+        .into_iter()
+        .filter(|object| object.last_modified < cutoff)
+        .filter(|object| {
+            let key = storage::variant_source(&object.key).unwrap_or(&object.key);
+            !referenced.contains(key)
+        })
+        .map(|object| object.key)
+        .collect();
+    orphaned.sort();
+
+    let mut deleted = Vec::new();
+    if delete {
+        for key in &orphaned {
+            match storage.delete(key).await {
+                Ok(()) => deleted.push(key.clone()),
+                Err(e) => eprintln!("Failed to delete orphaned object {}: {}", key, e),
+            }
+        }
+    }
+
+    Ok(Report {
+        deleted,
+        missing,
+        orphaned,
+    })
+}
+
+/// Garbage collects orphaned objects every `interval` for as long as the server runs
+pub fn spawn_periodic(
+    db: PgPool,
+    storage: Arc<dyn Storage>,
+    interval: std::time::Duration,
+    grace_period: Duration,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+
+            match run(&db, storage.as_ref(), grace_period, true).await {
+                Ok(report) => println!(
+                    "Reconciliation: {} missing, {} orphaned, {} deleted",
+                    report.missing.len(),
+                    report.orphaned.len(),
+                    report.deleted.len()
+                ),
+                Err(e) => eprintln!("Reconciliation failed: {}", e),
+            }
+        }
+    });
+}
@@ -1,7 +1,10 @@
 use crate::controllers;
 use crate::middlewares;
 use crate::models;
+use crate::storage;
 use axum::{
+    extract::DefaultBodyLimit,
+    handler::Handler,
     http::HeaderValue,
     middleware,
     routing::{delete, get, post, put},
@@ -12,9 +15,13 @@ use std::time::Duration;
 use tower_http::{
     compression::CompressionLayer,
     cors::{AllowOrigin, Any, CorsLayer},
+    limit::RequestBodyLimitLayer,
+    services::ServeDir,
     timeout::TimeoutLayer,
 };
 
+const MULTIPART_OVERHEAD: usize = 64 * 1024;
+
 pub fn create_route(state: &Arc<models::AppState>) -> Router {
     let cors = CorsLayer::new()
         .allow_origin(AllowOrigin::list([
@@ -27,6 +34,7 @@ pub fn create_route(state: &Arc<models::AppState>) -> Router {
     let auth_routes = Router::new()
         .route("/callback", get(controllers::auth::callback::handler))
         .route("/login", get(controllers::auth::login::handler))
+        .route("/login/{provider}", get(controllers::auth::login::handler))
         .route("/logout", get(controllers::auth::logout::handler))
         .route(
             "/me",
@@ -34,7 +42,8 @@ pub fn create_route(state: &Arc<models::AppState>) -> Router {
                 state.clone(),
                 middlewares::with_auth::handler,
             )),
-        );
+        )
+        .route("/refresh", post(controllers::auth::refresh::handler));
 
     let comment_routes = Router::new().route(
         "/post/{meme_id}",
@@ -44,6 +53,11 @@ pub fn create_route(state: &Arc<models::AppState>) -> Router {
         )),
     );
 
+    // Both files plus room for the boundaries and the small fields, the handler checks each
+    // field against its own limit as it streams in
+    let upload_limit =
+        state.config.max_upload_size() + state.config.bucket_object_max_size + MULTIPART_OVERHEAD;
+
     let meme_routes = Router::new()
         .route(
             "/delete/{id}",
@@ -57,16 +71,61 @@ pub fn create_route(state: &Arc<models::AppState>) -> Router {
                     middlewares::with_auth::handler,
                 )),
         )
+        .route(
+            "/deletions",
+            get(controllers::meme::deletions::handler)
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    middlewares::with_is_admin::handler,
+                ))
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    middlewares::with_auth::handler,
+                )),
+        )
+        .route(
+            "/finalize",
+            post(controllers::meme::finalize::handler).layer(middleware::from_fn_with_state(
+                state.clone(),
+                middlewares::with_auth::handler,
+            )),
+        )
         .route("/get", get(controllers::meme::get::handler))
         .route("/get/{id}", get(controllers::meme::get_by_id::handler))
+        .route(
+            "/metrics",
+            get(controllers::meme::metrics::handler)
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    middlewares::with_is_admin::handler,
+                ))
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    middlewares::with_auth::handler,
+                )),
+        )
         .route(
             "/post",
-            post(controllers::meme::post::handler).layer(middleware::from_fn_with_state(
+            post(
+                controllers::meme::post::handler
+                    .layer(DefaultBodyLimit::disable())
+                    .layer(RequestBodyLimitLayer::new(upload_limit)),
+            )
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                middlewares::with_auth::handler,
+            )),
+        )
+        .route(
+            "/presign",
+            post(controllers::meme::presign::handler).layer(middleware::from_fn_with_state(
                 state.clone(),
                 middlewares::with_auth::handler,
             )),
         );
 
+    let img_routes = Router::new().route("/{id}", get(controllers::img::get::handler));
+
     let like_routes = Router::new().route(
         "/post/{meme_id}",
         post(controllers::like::post::handler).layer(middleware::from_fn_with_state(
@@ -91,6 +150,40 @@ pub fn create_route(state: &Arc<models::AppState>) -> Router {
             )),
         );
 
+    let session_routes = Router::new()
+        .route(
+            "/delete/{id}",
+            delete(controllers::session::delete::handler).layer(middleware::from_fn_with_state(
+                state.clone(),
+                middlewares::with_auth::handler,
+            )),
+        )
+        .route(
+            "/delete-all/{user_id}",
+            delete(controllers::session::delete_all::handler)
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    middlewares::with_is_admin::handler,
+                ))
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    middlewares::with_auth::handler,
+                )),
+        )
+        .route(
+            "/delete-others",
+            delete(controllers::session::delete_others::handler).layer(
+                middleware::from_fn_with_state(state.clone(), middlewares::with_auth::handler),
+            ),
+        )
+        .route(
+            "/get",
+            get(controllers::session::get::handler).layer(middleware::from_fn_with_state(
+                state.clone(),
+                middlewares::with_auth::handler,
+            )),
+        );
+
     let user_routes = Router::new().route(
         "/put",
         put(controllers::user::put::handler).layer(middleware::from_fn_with_state(
@@ -99,15 +192,26 @@ pub fn create_route(state: &Arc<models::AppState>) -> Router {
         )),
     );
 
-    Router::new()
+    let mut router = Router::new();
+
+    if state.config.storage_backend == models::StorageBackend::Local {
+        router = router.nest_service(
+            storage::local::MOUNT_PATH,
+            ServeDir::new(&state.config.storage_local_dir),
+        );
+    }
+
+    router
         .nest(
             "/api",
             Router::new()
                 .nest("/auth", auth_routes)
                 .nest("/comment", comment_routes)
+                .nest("/img", img_routes)
                 .nest("/like", like_routes)
                 .nest("/meme", meme_routes)
                 .nest("/save", save_routes)
+                .nest("/session", session_routes)
                 .nest("/user", user_routes)
                 .with_state(state.clone()),
         )
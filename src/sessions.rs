@@ -0,0 +1,390 @@
+use crate::models::{Config, JWTClaims, User};
+use axum::http::{header, HeaderMap};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use rand::distr::Alphanumeric;
+use rand::{rng, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgPool;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+const USER_AGENT_MAX_LEN: usize = 256;
+
+/// `last_seen_at` is only written when it is older than this, not on every request
+const LAST_SEEN_RESOLUTION: Duration = Duration::minutes(1);
+
+/// How long the token a session was just rotated from still counts as a concurrent refresh,
+/// e.g. from another tab, rather than a leak
+const ROTATION_GRACE_SECS: f64 = 30.0;
+
+/// What the session list shows users to tell their devices apart
+pub struct Client {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl Client {
+    /// Behind the ingress the peer is the proxy and the client is the last forwarded address no
+    /// trusted proxy added. Anyone else could put any address in `X-Forwarded-For`
+    pub fn from_request(
+        headers: &HeaderMap,
+        peer: Option<IpAddr>,
+        trusted_proxies: &[TrustedProxy],
+    ) -> Self {
+        let forwarded: Vec<IpAddr> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|ip| ip.trim().parse().ok())
+            .collect();
+
+        let mut ip = peer;
+        for hop in forwarded.iter().rev() {
+            match ip {
+                Some(proxy)
+                    if trusted_proxies
+                        .iter()
+                        .any(|trusted| trusted.contains(proxy)) =>
+                {
+                    ip = Some(*hop)
+                }
+                _ => break,
+            }
+        }
+
+        let user_agent = headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(USER_AGENT_MAX_LEN).collect());
+
+        Self {
+            ip: ip.map(coarse_ip),
+            user_agent,
+        }
+    }
+}
+
+/// Address or network of a proxy whose `X-Forwarded-For` is believed, e.g. `10.0.0.0/8`
+#[derive(Clone, Copy)]
+pub struct TrustedProxy {
+    network: IpAddr,
+    prefix_len: u32,
+}
+
+impl TrustedProxy {
+    fn contains(&self, ip: IpAddr) -> bool {
+        let (network, ip, bits) = match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                (u32::from(network) as u128, u32::from(ip) as u128, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => (u128::from(network), u128::from(ip), 128),
+            _ => return false,
+        };
+
+        let host_bits = bits - self.prefix_len;
+        network.checked_shr(host_bits).unwrap_or(0) == ip.checked_shr(host_bits).unwrap_or(0)
+    }
+}
+
+impl FromStr for TrustedProxy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid TRUSTED_PROXIES env var: {}", s);
+
+        let (network, prefix_len) = match s.split_once('/') {
+            Some((network, prefix_len)) => (network, Some(prefix_len)),
+            None => (s, None),
+        };
+        let network: IpAddr = network.parse().map_err(|_| invalid())?;
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse().map_err(|_| invalid())?,
+            None => bits,
+        };
+
+        if prefix_len > bits {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+/// Only the network is kept, enough to recognize a location without storing the address
+fn coarse_ip(ip: IpAddr) -> String {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            format!("{:x}:{:x}:{:x}::/48", segments[0], segments[1], segments[2])
+        }
+    }
+}
+
+pub struct Tokens {
+    pub access_token: String,
+    /// `<session id>.<secret>`, only a hash of the secret is stored
+    pub refresh_token: String,
+}
+
+fn random_string(len: usize) -> String {
+    rng()
+        .sample_iter(Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+fn hash(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn access_token(config: &Config, user: &User, session_id: &str) -> Result<String, String> {
+    let claims = JWTClaims {
+        exp: (Utc::now() + Duration::seconds(config.session_access_ttl as i64)).timestamp()
+            as usize,
+        is_admin: user.is_admin,
+        sid: session_id.to_string(),
+        sub: user.id.clone(),
+        username: user.username.clone(),
+    };
+
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(config.jwt_secret.as_bytes()),
+    )
+    .map_err(|e| e.to_string())
+}
+
+pub async fn create(
+    db: &PgPool,
+    config: &Config,
+    user: &User,
+    client: &Client,
+) -> Result<Tokens, String> {
+    let id = random_string(32);
+    let secret = random_string(48);
+    let expires_at = Utc::now() + Duration::seconds(config.session_refresh_ttl as i64);
+
+    sqlx::query(
+        "
+        INSERT INTO sessions (id, user_id, refresh_token_hash, expires_at, user_agent, ip)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ",
+    )
+    .bind(&id)
+    .bind(&user.id)
+    .bind(hash(&secret))
+    .bind(expires_at)
+    .bind(&client.user_agent)
+    .bind(&client.ip)
+    .execute(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(Tokens {
+        access_token: access_token(config, user, &id)?,
+        refresh_token: format!("{}.{}", id, secret),
+    })
+}
+
+pub enum Refresh {
+    Rotated(Tokens),
+    /// Another request rotated the token a moment ago, the client should retry with the pair it
+    /// got
+    Superseded,
+    /// The session is gone or the token is wrong
+    Rejected,
+}
+
+/// Trades a refresh token for a new pair. A refresh token works only once, so the one it was
+/// rotated from means it leaked and the whole session is revoked, unless it comes right after the
+/// rotation
+pub async fn refresh(db: &PgPool, config: &Config, refresh_token: &str) -> Result<Refresh, String> {
+    let Some((id, secret)) = refresh_token.split_once('.') else {
+        return Ok(Refresh::Rejected);
+    };
+
+    let session: Option<(String, Option<String>, bool, String, bool, String)> = sqlx::query_as(
+        "
+        SELECT
+            sessions.refresh_token_hash,
+            sessions.previous_refresh_token_hash,
+            COALESCE(sessions.rotated_at > NOW() - make_interval(secs => $2), FALSE),
+            users.id,
+            users.is_admin,
+            users.username
+        FROM sessions
+        JOIN users ON users.id = sessions.user_id
+        WHERE sessions.id = $1 AND sessions.revoked_at IS NULL AND sessions.expires_at > NOW()
+        ",
+    )
+    .bind(id)
+    .bind(ROTATION_GRACE_SECS)
+    .fetch_optional(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let Some((
+        refresh_token_hash,
+        previous_refresh_token_hash,
+        just_rotated,
+        user_id,
+        is_admin,
+        username,
+    )) = session
+    else {
+        return Ok(Refresh::Rejected);
+    };
+
+    let secret_hash = hash(secret);
+
+    // Anyone can make up a token for a session id, only a rotated one proves a leak
+    if previous_refresh_token_hash.as_deref() == Some(secret_hash.as_str()) {
+        if just_rotated {
+            return Ok(Refresh::Superseded);
+        }
+
+        eprintln!("Refresh token reused, revoking session {}", id);
+        revoke(db, id).await?;
+        return Ok(Refresh::Rejected);
+    }
+
+    if refresh_token_hash != secret_hash {
+        return Ok(Refresh::Rejected);
+    }
+
+    let new_secret = random_string(48);
+    let expires_at = Utc::now() + Duration::seconds(config.session_refresh_ttl as i64);
+
+    // Matching on the old hash keeps two concurrent refreshes from both succeeding
+    let rotated = sqlx::query(
+        "
+        UPDATE sessions
+        SET
+            refresh_token_hash = $1,
+            previous_refresh_token_hash = $4,
+            rotated_at = NOW(),
+            expires_at = $2,
+            last_seen_at = NOW()
+        WHERE id = $3 AND refresh_token_hash = $4 AND revoked_at IS NULL
+        ",
+    )
+    .bind(hash(&new_secret))
+    .bind(expires_at)
+    .bind(id)
+    .bind(&refresh_token_hash)
+    .execute(db)
+    .await
+    .map_err(|e| e.to_string())?
+    .rows_affected();
+
+    // Another refresh won the race, or the session was revoked meanwhile and the retry says so
+    if rotated == 0 {
+        return Ok(Refresh::Superseded);
+    }
+
+    let user = User {
+        id: user_id,
+        is_admin,
+        username,
+    };
+
+    Ok(Refresh::Rotated(Tokens {
+        access_token: access_token(config, &user, id)?,
+        refresh_token: format!("{}.{}", id, new_secret),
+    }))
+}
+
+pub async fn revoke(db: &PgPool, session_id: &str) -> Result<(), String> {
+    sqlx::query("UPDATE sessions SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL")
+        .bind(session_id)
+        .execute(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Revokes the session a refresh token belongs to, only if the token is its current one. Returns
+/// whether it was revoked
+pub async fn revoke_with_refresh_token(db: &PgPool, refresh_token: &str) -> Result<bool, String> {
+    let Some((id, secret)) = refresh_token.split_once('.') else {
+        return Ok(false);
+    };
+
+    let result = sqlx::query(
+        "
+        UPDATE sessions
+        SET revoked_at = NOW()
+        WHERE id = $1 AND refresh_token_hash = $2 AND revoked_at IS NULL
+        ",
+    )
+    .bind(id)
+    .bind(hash(secret))
+    .execute(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Revokes every session of a user but `except`, returns how many were revoked
+pub async fn revoke_all(db: &PgPool, user_id: &str, except: Option<&str>) -> Result<u64, String> {
+    let result = sqlx::query(
+        "
+        UPDATE sessions
+        SET revoked_at = NOW()
+        WHERE user_id = $1 AND revoked_at IS NULL AND ($2::VARCHAR IS NULL OR id <> $2)
+        ",
+    )
+    .bind(user_id)
+    .bind(except)
+    .execute(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(result.rows_affected())
+}
+
+/// Checks the session is still active and records it was seen. Access tokens outlive a
+/// revocation by up to their TTL unless this runs on every request
+pub async fn touch(db: &PgPool, session_id: &str) -> Result<bool, String> {
+    let session: Option<(DateTime<Utc>,)> = sqlx::query_as(
+        "
+        SELECT last_seen_at FROM sessions
+        WHERE id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+        ",
+    )
+    .bind(session_id)
+    .fetch_optional(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let Some((last_seen_at,)) = session else {
+        return Ok(false);
+    };
+
+    if Utc::now() - last_seen_at > LAST_SEEN_RESOLUTION {
+        sqlx::query("UPDATE sessions SET last_seen_at = NOW() WHERE id = $1")
+            .bind(session_id)
+            .execute(db)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(true)
+}
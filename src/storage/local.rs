@@ -0,0 +1,117 @@
+use crate::models::Config;
+use crate::storage::{Storage, StoredObject};
+use async_trait::async_trait;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const MOUNT_PATH: &str = "/media";
+
+pub struct LocalStorage {
+    base_url: String,
+    dir: PathBuf,
+}
+
+impl LocalStorage {
+    pub async fn new(config: &Config) -> Result<Self, String> {
+        tokio::fs::create_dir_all(&config.storage_local_dir)
+            .await
+            .map_err(|e| format!("Failed to create {}: {}", config.storage_local_dir, e))?;
+
+        Ok(Self {
+            base_url: format!(
+                "{}{}",
+                config.storage_local_url.trim_end_matches('/'),
+                MOUNT_PATH
+            ),
+            dir: PathBuf::from(&config.storage_local_dir),
+        })
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, String> {
+        if key.is_empty() || key.split('/').any(|part| part.is_empty() || part == "..") {
+            return Err(format!("Invalid object key: {}", key));
+        }
+        Ok(self.dir.join(key))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, data: Vec<u8>, _content_type: &str) -> Result<(), String> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        tokio::fs::write(path, data)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        tokio::fs::read(self.path(key)?)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    async fn size(&self, key: &str) -> Result<u64, String> {
+        tokio::fs::metadata(self.path(key)?)
+            .await
+            .map(|metadata| metadata.len())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn list(&self) -> Result<Vec<StoredObject>, String> {
+        let mut objects = Vec::new();
+        let mut dirs = vec![self.dir.clone()];
+
+        while let Some(dir) = dirs.pop() {
+            let mut entries = tokio::fs::read_dir(&dir).await.map_err(|e| e.to_string())?;
+
+            while let Some(entry) = entries.next_entry().await.map_err(|e| e.to_string())? {
+                let metadata = entry.metadata().await.map_err(|e| e.to_string())?;
+
+                if metadata.is_dir() {
+                    dirs.push(entry.path());
+                    continue;
+                }
+
+                let key = entry
+                    .path()
+                    .strip_prefix(&self.dir)
+                    .map_err(|e| e.to_string())?
+                    .to_string_lossy()
+                    .replace('\\', "/");
+
+                objects.push(StoredObject {
+                    key,
+                    last_modified: metadata.modified().map_err(|e| e.to_string())?.into(),
+                });
+            }
+        }
+
+        Ok(objects)
+    }
+
+    async fn presign_put(
+        &self,
+        _key: &str,
+        _size: u64,
+        _expires_in: Duration,
+    ) -> Result<String, String> {
+        Err("Presigned uploads are only supported by the s3 storage backend".to_string())
+    }
+
+    fn public_url(&self, key: &str) -> String {
+        format!("{}/{}", self.base_url, key)
+    }
+}
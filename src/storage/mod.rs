@@ -0,0 +1,65 @@
+use crate::models::{Config, StorageBackend};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub mod local;
+pub mod s3;
+
+const VARIANTS_PREFIX: &str = "variants/";
+
+pub struct StoredObject {
+    pub key: String,
+    pub last_modified: DateTime<Utc>,
+}
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(&self, key: &str, data: Vec<u8>, content_type: &str) -> Result<(), String>;
+    async fn get(&self, key: &str) -> Result<Vec<u8>, String>;
+    async fn delete(&self, key: &str) -> Result<(), String>;
+    async fn size(&self, key: &str) -> Result<u64, String>;
+    async fn list(&self) -> Result<Vec<StoredObject>, String>;
+    /// URL clients can PUT the object to directly, without going through the server. Only a
+    /// body of exactly `size` bytes is accepted
+    async fn presign_put(
+        &self,
+        key: &str,
+        size: u64,
+        expires_in: Duration,
+    ) -> Result<String, String>;
+    fn public_url(&self, key: &str) -> String;
+}
+
+/// Names objects after the hash of their content, sharded so no prefix grows too large.
+/// Identical files share a key, so callers must check no other meme uses it before deleting
+pub fn content_key(data: &[u8], extension: &str) -> String {
+    let hash: String = Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    format!("{}/{}/{}.{}", &hash[..2], &hash[2..4], hash, extension)
+}
+
+/// Where encoded variants of `source_key` are cached, named after it so reconciliation can
+/// tell which ones belong to a deleted meme
+pub fn variant_key(source_key: &str, variant: &str) -> String {
+    format!("{}{}/{}", VARIANTS_PREFIX, source_key, variant)
+}
+
+/// Object key a cached variant was encoded from
+pub fn variant_source(key: &str) -> Option<&str> {
+    key.strip_prefix(VARIANTS_PREFIX)?
+        .rsplit_once('/')
+        .map(|(source_key, _)| source_key)
+}
+
+pub async fn from_config(config: &Config) -> Result<Arc<dyn Storage>, String> {
+    match config.storage_backend {
+        StorageBackend::Local => Ok(Arc::new(local::LocalStorage::new(config).await?)),
+        StorageBackend::S3 => Ok(Arc::new(s3::S3Storage::new(config).await?)),
+    }
+}
@@ -0,0 +1,166 @@
+use crate::create_bucket_client;
+use crate::models::Config;
+use crate::storage::{Storage, StoredObject};
+use async_trait::async_trait;
+use aws_sdk_s3::{presigning::PresigningConfig, primitives::ByteStream, Client};
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+pub struct S3Storage {
+    base_url: String,
+    bucket_name: String,
+    client: Client,
+}
+
+impl S3Storage {
+    /// Builds the client shared by every request and makes sure the bucket is reachable with it
+    pub async fn new(config: &Config) -> Result<Self, String> {
+        // Path style is what S3 compatible stand-ins like MinIO expect
+        let base_url = if config.bucket_force_path_style {
+            format!(
+                "{}/{}",
+                config.bucket_endpoint.trim_end_matches('/'),
+                config.bucket_name
+            )
+        } else {
+            let endpoint = config
+                .bucket_endpoint
+                .strip_prefix("https://")
+                .ok_or("BUCKET_ENDPOINT env var missing https:// prefix")?;
+            format!("https://{}.{}", config.bucket_name, endpoint)
+        };
+
+        let client = create_bucket_client(config);
+
+        client
+            .head_bucket()
+            .bucket(&config.bucket_name)
+            .send()
+            .await
+            .map_err(|e| format!("Bucket {} is not reachable: {:?}", config.bucket_name, e))?;
+
+        Ok(Self {
+            base_url,
+            bucket_name: config.bucket_name.clone(),
+            client,
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, data: Vec<u8>, content_type: &str) -> Result<(), String> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .body(ByteStream::from(data))
+            .content_type(content_type)
+            .acl("public-read".into())
+            .send()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        let object = self
+            .client
+            .get_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+
+        let data = object
+            .body
+            .collect()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+
+        Ok(data.into_bytes().to_vec())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+
+        Ok(())
+    }
+
+    async fn size(&self, key: &str) -> Result<u64, String> {
+        let object = self
+            .client
+            .head_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+
+        Ok(object.content_length().unwrap_or_default().max(0) as u64)
+    }
+
+    async fn list(&self) -> Result<Vec<StoredObject>, String> {
+        let mut objects = Vec::new();
+
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket_name)
+            .into_paginator()
+            .send();
+
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| format!("{:?}", e))?;
+
+            for object in page.contents() {
+                let (Some(key), Some(last_modified)) = (object.key(), object.last_modified())
+                else {
+                    continue;
+                };
+
+                objects.push(StoredObject {
+                    key: key.to_string(),
+                    last_modified: DateTime::<Utc>::from_timestamp(last_modified.secs(), 0)
+                        .unwrap_or_default(),
+                });
+            }
+        }
+
+        Ok(objects)
+    }
+
+    async fn presign_put(
+        &self,
+        key: &str,
+        size: u64,
+        expires_in: Duration,
+    ) -> Result<String, String> {
+        let presigning_config =
+            PresigningConfig::expires_in(expires_in).map_err(|e| e.to_string())?;
+
+        let request = self
+            .client
+            .put_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .content_length(size as i64)
+            .presigned(presigning_config)
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+
+        Ok(request.uri().to_string())
+    }
+
+    fn public_url(&self, key: &str) -> String {
+        format!("{}/{}", self.base_url, key)
+    }
+}
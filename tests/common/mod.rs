@@ -0,0 +1,40 @@
+#![allow(dead_code)]
+
+pub mod mock_provider;
+pub mod server;
+
+pub fn png(width: u32, height: u32) -> Vec<u8> {
+    let img = image::RgbaImage::from_fn(width, height, |x, y| {
+        image::Rgba([(x % 256) as u8, (y % 256) as u8, 128, 255])
+    });
+
+    let mut data = Vec::new();
+    image::DynamicImage::ImageRgba8(img)
+        .write_to(
+            &mut std::io::Cursor::new(&mut data),
+            image::ImageFormat::Png,
+        )
+        .expect("Error encoding png");
+    data
+}
+
+/// Animated GIF whose frames differ, so no encoder merges them
+pub fn gif(width: u32, height: u32, frames: u32) -> Vec<u8> {
+    let mut data = Vec::new();
+
+    {
+        let mut encoder = image::codecs::gif::GifEncoder::new(&mut data);
+        encoder
+            .encode_frames((0..frames).map(|i| {
+                let img = image::RgbaImage::from_pixel(
+                    width,
+                    height,
+                    image::Rgba([(i * 40 % 256) as u8, 0, 128, 255]),
+                );
+                image::Frame::new(img)
+            }))
+            .expect("Error encoding gif");
+    }
+
+    data
+}
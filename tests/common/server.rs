@@ -0,0 +1,267 @@
+use super::mock_provider::{self, MockProvider};
+use reqwest::{
+    header::{COOKIE, LOCATION, SET_COOKIE, USER_AGENT},
+    multipart::{Form, Part},
+    redirect::Policy,
+    RequestBuilder, StatusCode, Url,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::process::{Child, Command};
+
+pub const CLIENT_URL: &str = "http://localhost:5173";
+pub const TEST_USER_AGENT: &str = "memelibre-test";
+
+const SCHEMA: &str = include_str!("../../schema.sql");
+
+#[derive(Deserialize)]
+pub struct User {
+    pub id: String,
+    pub username: String,
+}
+
+/// The real server binary on its own database, logging in through the mock provider as
+/// `mock` (OpenID Connect) and `plain` (OAuth with a userinfo endpoint only)
+pub struct TestServer {
+    admin: PgPool,
+    pub base_url: String,
+    pub db: PgPool,
+    db_name: String,
+    _process: Child,
+    pub provider: MockProvider,
+}
+
+impl TestServer {
+    /// `envs` override the defaults. Needs a Postgres in `TEST_DB_CONN_STRING` to create
+    /// databases in
+    pub async fn start(envs: &[(&str, &str)]) -> Self {
+        let conn_string = std::env::var("TEST_DB_CONN_STRING")
+            .expect("TEST_DB_CONN_STRING must point to a Postgres to create test databases in");
+
+        let provider = MockProvider::spawn().await;
+
+        let admin = PgPool::connect(&conn_string).await.unwrap();
+        let db_name = format!("memelibre_test_{}", rand::random::<u32>());
+        sqlx::query(&format!("CREATE DATABASE {}", db_name))
+            .execute(&admin)
+            .await
+            .unwrap();
+
+        let mut db_url = Url::parse(&conn_string).unwrap();
+        db_url.set_path(&db_name);
+
+        let db = PgPool::connect(db_url.as_str()).await.unwrap();
+        sqlx::raw_sql(SCHEMA).execute(&db).await.unwrap();
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let base_url = format!("http://127.0.0.1:{}", port);
+        let media_dir = std::env::temp_dir().join(&db_name);
+
+        let process = Command::new(env!("CARGO_BIN_EXE_memelibre_server"))
+            .envs([
+                ("BUCKET_OBJECT_MAX_SIZE", "5000000"),
+                ("CLIENT_URL", CLIENT_URL),
+                ("COMPRESSION_QUALITY", "75"),
+                ("DB_CONN_STRING", db_url.as_str()),
+                ("DB_MAX_CONN", "2"),
+                ("JWT_SECRET", "secret"),
+                ("MEMES_PULL_LIMIT", "10"),
+                ("OAUTH_MOCK_CLIENT_ID", mock_provider::CLIENT_ID),
+                ("OAUTH_MOCK_CLIENT_SECRET", mock_provider::CLIENT_SECRET),
+                ("OAUTH_MOCK_ISSUER", &provider.issuer),
+                (
+                    "OAUTH_PLAIN_AUTHORIZATION_URL",
+                    &format!("{}/authorize", provider.issuer),
+                ),
+                ("OAUTH_PLAIN_CLIENT_ID", mock_provider::CLIENT_ID),
+                ("OAUTH_PLAIN_CLIENT_SECRET", mock_provider::CLIENT_SECRET),
+                ("OAUTH_PLAIN_SCOPE", "profile"),
+                (
+                    "OAUTH_PLAIN_TOKEN_URL",
+                    &format!("{}/token", provider.issuer),
+                ),
+                (
+                    "OAUTH_PLAIN_USERINFO_URL",
+                    &format!("{}/userinfo", provider.issuer),
+                ),
+                ("OAUTH_PROVIDERS", "mock,plain"),
+                (
+                    "OAUTH_REDIRECT_URI",
+                    &format!("{}/api/auth/callback", base_url),
+                ),
+                ("PORT", &port.to_string()),
+                ("STORAGE_BACKEND", "local"),
+                ("STORAGE_LOCAL_DIR", media_dir.to_str().unwrap()),
+                ("TIMEOUT_DURATION", "10"),
+            ])
+            .envs(envs.iter().copied())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+
+        let http = reqwest::Client::new();
+        for _ in 0..100 {
+            if http.get(&base_url).send().await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        Self {
+            admin,
+            base_url,
+            db,
+            db_name,
+            _process: process,
+            provider,
+        }
+    }
+
+    pub async fn count(&self, table: &str) -> i64 {
+        let (count,): (i64,) = sqlx::query_as(&format!("SELECT COUNT(*) FROM {}", table))
+            .fetch_one(&self.db)
+            .await
+            .unwrap();
+        count
+    }
+
+    pub async fn stop(self) {
+        drop(self._process);
+        self.db.close().await;
+        sqlx::query(&format!("DROP DATABASE {} WITH (FORCE)", self.db_name))
+            .execute(&self.admin)
+            .await
+            .unwrap();
+        let _ = std::fs::remove_dir_all(std::env::temp_dir().join(&self.db_name));
+    }
+}
+
+/// Keeps cookies across requests and never follows redirects, so every hop can be inspected
+pub struct Browser {
+    pub cookies: HashMap<String, String>,
+    http: reqwest::Client,
+}
+
+impl Browser {
+    pub fn new() -> Self {
+        Self {
+            cookies: HashMap::new(),
+            http: reqwest::Client::builder()
+                .redirect(Policy::none())
+                .build()
+                .unwrap(),
+        }
+    }
+
+    pub async fn get(&mut self, url: &str) -> reqwest::Response {
+        self.send(self.http.get(url)).await
+    }
+
+    pub async fn post(&mut self, url: &str) -> reqwest::Response {
+        self.send(self.http.post(url)).await
+    }
+
+    pub async fn post_json<T: Serialize>(&mut self, url: &str, body: &T) -> reqwest::Response {
+        self.send(self.http.post(url).json(body)).await
+    }
+
+    pub async fn post_multipart(&mut self, url: &str, form: Form) -> reqwest::Response {
+        self.send(self.http.post(url).multipart(form)).await
+    }
+
+    async fn send(&mut self, request: RequestBuilder) -> reqwest::Response {
+        let mut request = request.header(USER_AGENT, TEST_USER_AGENT);
+
+        if !self.cookies.is_empty() {
+            let cookie = self
+                .cookies
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join("; ");
+            request = request.header(COOKIE, cookie);
+        }
+
+        let response = request.send().await.unwrap();
+
+        for set_cookie in response.headers().get_all(SET_COOKIE) {
+            let pair = set_cookie.to_str().unwrap().split(';').next().unwrap();
+            let (name, value) = pair.split_once('=').unwrap();
+
+            if value.is_empty() {
+                self.cookies.remove(name);
+            } else {
+                self.cookies.insert(name.to_string(), value.to_string());
+            }
+        }
+
+        response
+    }
+
+    /// Goes through the provider up to the point it sends the browser back, returns the
+    /// callback URL
+    pub async fn start_login(
+        &mut self,
+        server: &TestServer,
+        provider: &str,
+        subject: &str,
+    ) -> String {
+        let response = self
+            .get(&format!("{}/api/auth/login/{}", server.base_url, provider))
+            .await;
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+
+        let authorize_url = location(&response);
+        assert!(authorize_url.starts_with(&server.provider.issuer));
+        assert!(authorize_url.contains("code_challenge_method=S256"));
+
+        let response = self
+            .get(&format!("{}&login_hint={}", authorize_url, subject))
+            .await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+        location(&response)
+    }
+
+    pub async fn login(&mut self, server: &TestServer, provider: &str, subject: &str) -> User {
+        let callback_url = self.start_login(server, provider, subject).await;
+
+        let response = self.get(&callback_url).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(location(&response), CLIENT_URL);
+
+        self.me(server).await.unwrap()
+    }
+
+    /// Multipart upload of `file_data` as the meme's `file`
+    pub async fn upload(&mut self, server: &TestServer, file_data: Vec<u8>) -> reqwest::Response {
+        let form = Form::new().part("file", Part::bytes(file_data).file_name("meme"));
+        self.post_multipart(&format!("{}/api/meme/post", server.base_url), form)
+            .await
+    }
+
+    pub async fn me(&mut self, server: &TestServer) -> Option<User> {
+        let response = self.get(&format!("{}/api/auth/me", server.base_url)).await;
+
+        match response.status() {
+            StatusCode::OK => Some(response.json().await.unwrap()),
+            _ => None,
+        }
+    }
+}
+
+pub fn location(response: &reqwest::Response) -> String {
+    response
+        .headers()
+        .get(LOCATION)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
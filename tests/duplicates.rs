@@ -0,0 +1,116 @@
+mod common;
+
+use common::server::{Browser, TestServer};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+struct DuplicateRes {
+    duplicate_of: i32,
+}
+
+/// Advisory lock the server takes around its duplicate check and insert
+const DUPLICATES_LOCK: i64 = 0x6d656d65;
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn rejected_duplicate_keeps_the_objects_of_the_original() {
+    let server = TestServer::start(&[]).await;
+
+    let mut first = Browser::new();
+    first.login(&server, "mock", "alice").await;
+    let mut second = Browser::new();
+    second.login(&server, "mock", "bob").await;
+
+    // Holding the duplicates lock lets both copies pass the check made before storing, so the
+    // second one is only rejected once it stored the same content addressed objects and queues
+    // them for deletion while the first one references them
+    let mut lock = server.db.begin().await.unwrap();
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(DUPLICATES_LOCK)
+        .execute(&mut *lock)
+        .await
+        .unwrap();
+
+    let file_data = common::png(400, 300);
+    let release = async {
+        loop {
+            let (waiting,): (i64,) = sqlx::query_as(
+                "SELECT COUNT(*) FROM pg_locks WHERE locktype = 'advisory' AND NOT granted",
+            )
+            .fetch_one(&server.db)
+            .await
+            .unwrap();
+            if waiting == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        lock.commit().await.unwrap();
+    };
+
+    let (first_response, second_response, ()) = tokio::join!(
+        first.upload(&server, file_data.clone()),
+        second.upload(&server, file_data),
+        release
+    );
+
+    let mut statuses = [first_response.status(), second_response.status()];
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::CREATED, StatusCode::CONFLICT]);
+
+    let rejected = match first_response.status() {
+        StatusCode::CONFLICT => first_response,
+        _ => second_response,
+    };
+
+    let (original_id,): (i32,) = sqlx::query_as("SELECT id FROM memes")
+        .fetch_one(&server.db)
+        .await
+        .unwrap();
+    let duplicate: DuplicateRes = rejected.json().await.unwrap();
+    assert_eq!(duplicate.duplicate_of, original_id);
+
+    let object_keys: Vec<String> =
+        sqlx::query_scalar("SELECT object_key FROM meme_renditions WHERE meme_id = $1")
+            .bind(original_id)
+            .fetch_all(&server.db)
+            .await
+            .unwrap();
+    assert!(!object_keys.is_empty());
+
+    let (queued,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM pending_deletions WHERE object_key = ANY($1)")
+            .bind(&object_keys)
+            .fetch_one(&server.db)
+            .await
+            .unwrap();
+    assert_eq!(queued, object_keys.len() as i64);
+
+    // The worker is woken up by the rejection, it completes the rows without deleting anything
+    let mut pending = i64::MAX;
+    for _ in 0..50 {
+        (pending,) =
+            sqlx::query_as("SELECT COUNT(*) FROM pending_deletions WHERE completed_at IS NULL")
+                .fetch_one(&server.db)
+                .await
+                .unwrap();
+        if pending == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(pending, 0);
+
+    for object_key in &object_keys {
+        let response = first
+            .get(&format!("{}/media/{}", server.base_url, object_key))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", object_key);
+    }
+
+    assert_eq!(server.count("memes").await, 1);
+
+    server.stop().await;
+}
@@ -0,0 +1,265 @@
+mod common;
+
+use common::mock_provider::Tamper;
+use common::server::{Browser, TestServer, TEST_USER_AGENT};
+use reqwest::{StatusCode, Url};
+
+fn with_query_param(url: &str, name: &str, value: &str) -> String {
+    let mut url = Url::parse(url).unwrap();
+    let params: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(key, current)| match key == name {
+            true => (key.to_string(), value.to_string()),
+            false => (key.to_string(), current.to_string()),
+        })
+        .collect();
+    url.query_pairs_mut().clear().extend_pairs(params);
+    url.to_string()
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn first_login_creates_user_identity_and_session() {
+    let server = TestServer::start(&[]).await;
+
+    let mut browser = Browser::new();
+    let user = browser.login(&server, "mock", "alice").await;
+
+    assert!(!user.username.is_empty());
+    assert!(browser.cookies.contains_key("session_token"));
+    assert!(browser.cookies.contains_key("refresh_token"));
+    assert!(!browser
+        .cookies
+        .keys()
+        .any(|name| name.starts_with("oauth_")));
+
+    let (user_id,): (String,) = sqlx::query_as(
+        "SELECT user_id FROM identities WHERE provider = 'mock' AND subject = 'alice'",
+    )
+    .fetch_one(&server.db)
+    .await
+    .unwrap();
+    assert_eq!(user_id, user.id);
+
+    let (user_agent, ip): (Option<String>, Option<String>) =
+        sqlx::query_as("SELECT user_agent, ip FROM sessions WHERE user_id = $1")
+            .bind(&user.id)
+            .fetch_one(&server.db)
+            .await
+            .unwrap();
+    assert_eq!(user_agent.as_deref(), Some(TEST_USER_AGENT));
+    assert_eq!(ip.as_deref(), Some("127.0.0.0/24"));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn returning_login_reuses_the_user() {
+    let server = TestServer::start(&[]).await;
+
+    let first = Browser::new().login(&server, "mock", "alice").await;
+    let second = Browser::new().login(&server, "mock", "alice").await;
+
+    assert_eq!(first.id, second.id);
+    assert_eq!(first.username, second.username);
+    assert_eq!(server.count("users").await, 1);
+    assert_eq!(server.count("identities").await, 1);
+    assert_eq!(server.count("sessions").await, 2);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn username_collision_draws_another_username() {
+    let server = TestServer::start(&[]).await;
+
+    let alice = Browser::new().login(&server, "mock", "alice").await;
+
+    // Generated usernames are random, so the next insert is made to collide with alice's
+    sqlx::raw_sql(
+        "
+        CREATE TABLE forced_collisions (remaining INTEGER NOT NULL);
+        INSERT INTO forced_collisions VALUES (1);
+
+        CREATE FUNCTION force_username_collision() RETURNS trigger AS $$
+        BEGIN
+            IF (SELECT remaining FROM forced_collisions) > 0 THEN
+                UPDATE forced_collisions SET remaining = remaining - 1;
+                NEW.username := (SELECT username FROM users LIMIT 1);
+            END IF;
+            RETURN NEW;
+        END
+        $$ LANGUAGE plpgsql;
+
+        CREATE TRIGGER force_username_collision BEFORE INSERT ON users
+        FOR EACH ROW EXECUTE FUNCTION force_username_collision();
+        ",
+    )
+    .execute(&server.db)
+    .await
+    .unwrap();
+
+    let bob = Browser::new().login(&server, "mock", "bob").await;
+
+    let (remaining,): (i32,) = sqlx::query_as("SELECT remaining FROM forced_collisions")
+        .fetch_one(&server.db)
+        .await
+        .unwrap();
+    assert_eq!(remaining, 0);
+
+    assert_ne!(alice.id, bob.id);
+    assert_ne!(alice.username, bob.username);
+    assert_eq!(server.count("users").await, 2);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn mismatched_state_is_rejected() {
+    let server = TestServer::start(&[]).await;
+
+    let mut browser = Browser::new();
+    let callback_url = browser.start_login(&server, "mock", "alice").await;
+
+    let response = browser
+        .get(&with_query_param(&callback_url, "state", "forged"))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // The same callback in a browser that never started a login has no state cookie
+    let response = Browser::new().get(&callback_url).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    assert!(browser.me(&server).await.is_none());
+    assert_eq!(server.count("users").await, 0);
+    assert_eq!(server.count("sessions").await, 0);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn code_from_another_login_is_rejected() {
+    let server = TestServer::start(&[]).await;
+
+    // An attacker's code planted in the victim's browser along with the victim's own state
+    // still fails, the victim's PKCE verifier doesn't match the attacker's challenge
+    let attacker_callback = Browser::new().start_login(&server, "mock", "mallory").await;
+
+    let mut victim = Browser::new();
+    let victim_callback = victim.start_login(&server, "mock", "alice").await;
+    let victim_state = Url::parse(&victim_callback)
+        .unwrap()
+        .query_pairs()
+        .find(|(name, _)| name == "state")
+        .unwrap()
+        .1
+        .to_string();
+
+    let response = victim
+        .get(&with_query_param(
+            &attacker_callback,
+            "state",
+            &victim_state,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    assert!(victim.me(&server).await.is_none());
+    assert_eq!(server.count("users").await, 0);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn reused_code_is_rejected() {
+    let server = TestServer::start(&[]).await;
+
+    let mut browser = Browser::new();
+    let callback_url = browser.start_login(&server, "mock", "alice").await;
+    let cookies = browser.cookies.clone();
+
+    let response = browser.get(&callback_url).await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+    // Replaying the callback with the login cookies it consumed, the provider only takes a
+    // code once
+    let mut replay = Browser::new();
+    replay.cookies = cookies;
+    let response = replay.get(&callback_url).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    assert_eq!(server.count("sessions").await, 1);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn tampered_id_token_is_rejected() {
+    let server = TestServer::start(&[]).await;
+
+    for tamper in [Tamper::Audience, Tamper::Issuer, Tamper::Nonce] {
+        server.provider.tamper_next_id_token(tamper);
+
+        let mut browser = Browser::new();
+        let callback_url = browser.start_login(&server, "mock", "alice").await;
+
+        let response = browser.get(&callback_url).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{:?}", tamper);
+        assert!(browser.me(&server).await.is_none(), "{:?}", tamper);
+    }
+
+    assert_eq!(server.count("users").await, 0);
+    assert_eq!(server.count("sessions").await, 0);
+
+    // The provider is back to honest tokens
+    Browser::new().login(&server, "mock", "alice").await;
+    assert_eq!(server.count("users").await, 1);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn another_provider_is_linked_to_the_signed_in_user() {
+    let server = TestServer::start(&[]).await;
+
+    let mut browser = Browser::new();
+    let user = browser.login(&server, "mock", "alice").await;
+    let linked = browser.login(&server, "plain", "alice-plain").await;
+    assert_eq!(user.id, linked.id);
+
+    // The linked identity signs in as the same user from a fresh browser
+    let returning = Browser::new().login(&server, "plain", "alice-plain").await;
+    assert_eq!(user.id, returning.id);
+
+    assert_eq!(server.count("users").await, 1);
+    assert_eq!(server.count("identities").await, 2);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn id_token_naming_an_issuer_alias_is_accepted() {
+    let server = TestServer::start(&[("OAUTH_MOCK_ISSUER_ALIASES", "mock.example")]).await;
+
+    server.provider.next_id_token_issuer("mock.example");
+    Browser::new().login(&server, "mock", "alice").await;
+
+    // Aliases are the only other issuers let through
+    server.provider.next_id_token_issuer("other.example");
+    let mut browser = Browser::new();
+    let callback_url = browser.start_login(&server, "mock", "bob").await;
+    let response = browser.get(&callback_url).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    assert_eq!(server.count("users").await, 1);
+
+    server.stop().await;
+}
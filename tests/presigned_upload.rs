@@ -0,0 +1,202 @@
+mod common;
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::{head, put},
+    Router,
+};
+use common::server::{Browser, TestServer};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+type Objects = Arc<Mutex<HashMap<String, Bytes>>>;
+
+/// Bare bones S3 compatible stand-in, enough for path style object CRUD
+async fn spawn_fake_s3() -> (String, Objects) {
+    let objects = Objects::default();
+
+    let app = Router::new()
+        .route("/{bucket}", head(|| async { StatusCode::OK }))
+        .route("/{bucket}/", head(|| async { StatusCode::OK }))
+        .route(
+            "/{bucket}/{*key}",
+            put(
+                |State(objects): State<Objects>, Path((_, key)): Path<(String, String)>, body: Bytes| async move {
+                    objects.lock().unwrap().insert(key, body);
+                    StatusCode::OK
+                },
+            )
+            .get(
+                |State(objects): State<Objects>, Path((_, key)): Path<(String, String)>| async move {
+                    match objects.lock().unwrap().get(&key) {
+                        Some(body) => body.clone().into_response(),
+                        None => StatusCode::NOT_FOUND.into_response(),
+                    }
+                },
+            )
+            .head(
+                |State(objects): State<Objects>, Path((_, key)): Path<(String, String)>| async move {
+                    match objects.lock().unwrap().get(&key) {
+                        Some(body) => [(header::CONTENT_LENGTH, body.len())].into_response(),
+                        None => StatusCode::NOT_FOUND.into_response(),
+                    }
+                },
+            )
+            .delete(
+                |State(objects): State<Objects>, Path((_, key)): Path<(String, String)>| async move {
+                    objects.lock().unwrap().remove(&key);
+                    StatusCode::NO_CONTENT
+                },
+            ),
+        )
+        .with_state(objects.clone());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    (endpoint, objects)
+}
+
+#[derive(Serialize)]
+struct PresignReq {
+    size: usize,
+}
+
+#[derive(Deserialize)]
+struct PresignRes {
+    key: String,
+    upload_url: String,
+}
+
+#[derive(Serialize)]
+struct FinalizeReq {
+    key: String,
+}
+
+async fn start_server() -> (TestServer, Objects) {
+    let (endpoint, objects) = spawn_fake_s3().await;
+
+    let server = TestServer::start(&[
+        ("BUCKET_ENDPOINT", &endpoint),
+        ("BUCKET_FORCE_PATH_STYLE", "true"),
+        ("BUCKET_KEY", "key"),
+        ("BUCKET_NAME", "memes"),
+        ("BUCKET_REGION", "us-east-1"),
+        ("BUCKET_SECRET", "secret"),
+        ("STORAGE_BACKEND", "s3"),
+    ])
+    .await;
+
+    (server, objects)
+}
+
+/// Phase one, the client uploads straight to the bucket through a presigned URL
+async fn stage(server: &TestServer, browser: &mut Browser, file_data: &[u8]) -> String {
+    let response = browser
+        .post_json(
+            &format!("{}/api/meme/presign", server.base_url),
+            &PresignReq {
+                size: file_data.len(),
+            },
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let presigned: PresignRes = response.json().await.unwrap();
+
+    // The signature covers the declared size, the bucket turns any other body down
+    let signed_headers = reqwest::Url::parse(&presigned.upload_url)
+        .unwrap()
+        .query_pairs()
+        .find(|(name, _)| name == "X-Amz-SignedHeaders")
+        .unwrap()
+        .1
+        .to_string();
+    assert!(signed_headers
+        .split(';')
+        .any(|name| name == "content-length"));
+
+    let response = reqwest::Client::new()
+        .put(&presigned.upload_url)
+        .body(file_data.to_vec())
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    presigned.key
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn staged_upload_is_processed_and_cleaned_up() {
+    let (server, objects) = start_server().await;
+    let mut browser = Browser::new();
+    browser.login(&server, "mock", "alice").await;
+
+    let response = browser
+        .post_json(
+            &format!("{}/api/meme/presign", server.base_url),
+            &PresignReq { size: 100_000_000 },
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let key = stage(&server, &mut browser, &common::png(800, 600)).await;
+    assert!(objects.lock().unwrap().contains_key(&key));
+
+    // Phase two, finalize pulls the staged object back through the regular pipeline
+    let response = browser
+        .post_json(
+            &format!("{}/api/meme/finalize", server.base_url),
+            &FinalizeReq { key: key.clone() },
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(server.count("memes").await, 1);
+
+    let stored: Vec<(String,)> = sqlx::query_as("SELECT object_key FROM meme_renditions")
+        .fetch_all(&server.db)
+        .await
+        .unwrap();
+    assert_eq!(stored.len(), 3);
+
+    {
+        let objects = objects.lock().unwrap();
+        assert!(!objects.contains_key(&key));
+        for (object_key,) in stored {
+            assert!(objects.contains_key(&object_key));
+        }
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn upload_staged_by_someone_else_is_forbidden() {
+    let (server, objects) = start_server().await;
+
+    let mut alice = Browser::new();
+    alice.login(&server, "mock", "alice").await;
+    let key = stage(&server, &mut alice, &common::png(800, 600)).await;
+
+    let mut mallory = Browser::new();
+    mallory.login(&server, "mock", "mallory").await;
+    let response = mallory
+        .post_json(
+            &format!("{}/api/meme/finalize", server.base_url),
+            &FinalizeReq { key: key.clone() },
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    assert_eq!(server.count("memes").await, 0);
+    assert!(objects.lock().unwrap().contains_key(&key));
+
+    server.stop().await;
+}
@@ -0,0 +1,177 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::server::{Browser, TestServer};
+use memelibre_server::processing::pool::Pool;
+use reqwest::multipart::{Form, Part};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn runs_at_most_workers_jobs_at_once() {
+    let workers = 2;
+    let uploads = 8;
+    let pool = Arc::new(Pool::new(workers, uploads));
+    let running = Arc::new(AtomicUsize::new(0));
+    let max_running = Arc::new(AtomicUsize::new(0));
+
+    let burst: Vec<_> = (0..uploads)
+        .map(|_| {
+            let pool = pool.clone();
+            let running = running.clone();
+            let max_running = max_running.clone();
+            tokio::spawn(async move {
+                pool.run(move || {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    Ok(())
+                })
+                .await
+            })
+        })
+        .collect();
+
+    for upload in burst {
+        assert!(upload.await.unwrap().is_ok());
+    }
+
+    assert_eq!(max_running.load(Ordering::SeqCst), workers);
+    assert_eq!(pool.metrics().processed, uploads as u64);
+}
+
+#[tokio::test]
+async fn rejects_uploads_once_the_queue_is_full() {
+    let pool = Arc::new(Pool::new(1, 1));
+
+    let queued: Vec<_> = (0..2)
+        .map(|_| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                pool.run(|| {
+                    std::thread::sleep(Duration::from_millis(200));
+                    Ok(())
+                })
+                .await
+            })
+        })
+        .collect();
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let rejected = pool.run(|| Ok(())).await;
+    assert_eq!(rejected.unwrap_err().0, StatusCode::SERVICE_UNAVAILABLE);
+
+    for upload in queued {
+        assert!(upload.await.unwrap().is_ok());
+    }
+
+    let metrics = pool.metrics();
+    assert_eq!(metrics.processed, 2);
+    assert_eq!(metrics.rejected, 1);
+    assert_eq!(metrics.in_flight, 0);
+    assert_eq!(metrics.queued, 0);
+}
+
+/// Fetches the feed until `uploads` are done, returns the slowest response, the upload statuses
+/// and how many times the feed was fetched
+async fn probe_feed(
+    browser: &mut Browser,
+    server: &TestServer,
+    uploads: &mut JoinSet<reqwest::StatusCode>,
+) -> (Duration, Vec<reqwest::StatusCode>, usize) {
+    let mut slowest = Duration::ZERO;
+    let mut probes = 0;
+    let mut upload_statuses = Vec::new();
+
+    while !uploads.is_empty() {
+        let started = Instant::now();
+        let response = browser
+            .get(&format!("{}/api/meme/get", server.base_url))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        slowest = slowest.max(started.elapsed());
+        probes += 1;
+
+        while let Some(status) = uploads.try_join_next() {
+            upload_statuses.push(status.unwrap());
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    (slowest, upload_statuses, probes)
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn feed_stays_fast_while_uploads_saturate_the_pool() {
+    let workers = 1;
+    let queue = 3;
+    let uploads = 6;
+    let server = TestServer::start(&[
+        ("DUPLICATE_ACTION", "off"),
+        ("PROCESSING_QUEUE", &queue.to_string()),
+        ("PROCESSING_WORKERS", &workers.to_string()),
+        ("TIMEOUT_DURATION", "120"),
+    ])
+    .await;
+
+    let mut browser = Browser::new();
+    browser.login(&server, "mock", "alice").await;
+
+    let mut idle = Duration::ZERO;
+    for _ in 0..5 {
+        let started = Instant::now();
+        let response = browser
+            .get(&format!("{}/api/meme/get", server.base_url))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        idle = idle.max(started.elapsed());
+    }
+
+    // Large images keep the single worker busy for a while and overflow the queue
+    let url = format!("{}/api/meme/post", server.base_url);
+    let mut burst = JoinSet::new();
+    for _ in 0..uploads {
+        let mut uploader = Browser::new();
+        uploader.cookies = browser.cookies.clone();
+        let url = url.clone();
+        let file_data = common::png(1000, 1000);
+
+        burst.spawn(async move {
+            let form = Form::new().part("file", Part::bytes(file_data).file_name("meme"));
+            uploader.post_multipart(&url, form).await.status()
+        });
+    }
+
+    let (slowest, statuses, probes) = probe_feed(&mut browser, &server, &mut burst).await;
+
+    let created = statuses
+        .iter()
+        .filter(|&&status| status == StatusCode::CREATED)
+        .count();
+    let rejected = statuses
+        .iter()
+        .filter(|&&status| status == StatusCode::SERVICE_UNAVAILABLE)
+        .count();
+    assert_eq!(created + rejected, uploads as usize, "{:?}", statuses);
+    assert!(rejected > 0, "the pool never filled up");
+    assert!(
+        probes > 1,
+        "the uploads were over before the feed was fetched"
+    );
+
+    // Encoding happens off the async runtime, so the feed is only as slow as a database query
+    assert!(
+        slowest < idle + Duration::from_millis(500),
+        "feed took {:?} during the upload burst against {:?} idle",
+        slowest,
+        idle
+    );
+    assert_eq!(server.count("memes").await, created as i64);
+
+    server.stop().await;
+}
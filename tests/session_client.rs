@@ -0,0 +1,59 @@
+use axum::http::{HeaderMap, HeaderValue};
+use memelibre_server::sessions::{Client, TrustedProxy};
+use std::net::IpAddr;
+
+fn client_ip(forwarded_for: &str, peer: &str, trusted_proxies: &[&str]) -> Option<String> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "x-forwarded-for",
+        HeaderValue::from_str(forwarded_for).unwrap(),
+    );
+
+    let peer: IpAddr = peer.parse().unwrap();
+    let trusted_proxies: Vec<TrustedProxy> = trusted_proxies
+        .iter()
+        .map(|proxy| proxy.parse().unwrap())
+        .collect();
+
+    Client::from_request(&headers, Some(peer), &trusted_proxies).ip
+}
+
+#[test]
+fn forwarded_for_is_ignored_without_trusted_proxies() {
+    assert_eq!(
+        client_ip("1.2.3.4", "203.0.113.7", &[]),
+        Some("203.0.113.0/24".to_string())
+    );
+}
+
+#[test]
+fn forwarded_for_is_ignored_from_untrusted_peers() {
+    assert_eq!(
+        client_ip("1.2.3.4", "203.0.113.7", &["10.0.0.0/8"]),
+        Some("203.0.113.0/24".to_string())
+    );
+}
+
+#[test]
+fn client_is_the_last_address_no_trusted_proxy_added() {
+    // The first entry is whatever the client claimed, the proxies append what they saw
+    assert_eq!(
+        client_ip(
+            "1.2.3.4, 198.51.100.9, 10.0.0.5",
+            "10.1.2.3",
+            &["10.0.0.0/8"]
+        ),
+        Some("198.51.100.0/24".to_string())
+    );
+    assert_eq!(
+        client_ip("2001:db8:1:2::9", "fd00::1", &["fd00::/8"]),
+        Some("2001:db8:1::/48".to_string())
+    );
+}
+
+#[test]
+fn invalid_trusted_proxies_are_rejected() {
+    for proxy in ["10.0.0.0/33", "fd00::/129", "not-an-ip", "10.0.0.0/"] {
+        assert!(proxy.parse::<TrustedProxy>().is_err(), "{}", proxy);
+    }
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use common::server::{Browser, TestServer};
+use reqwest::StatusCode;
+
+/// A browser holding only a refresh token, without the access token that would name the session
+fn with_refresh_token(refresh_token: &str) -> Browser {
+    let mut browser = Browser::new();
+    browser
+        .cookies
+        .insert("refresh_token".to_string(), refresh_token.to_string());
+    browser
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn made_up_refresh_token_neither_refreshes_nor_revokes() {
+    let server = TestServer::start(&[]).await;
+
+    let mut alice = Browser::new();
+    alice.login(&server, "mock", "alice").await;
+    let refresh_token = alice.cookies["refresh_token"].clone();
+    let (session_id, _) = refresh_token.split_once('.').unwrap();
+    let forged = format!("{}.made-up", session_id);
+
+    let response = with_refresh_token(&forged)
+        .post(&format!("{}/api/auth/refresh", server.base_url))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    with_refresh_token(&forged)
+        .get(&format!("{}/api/auth/logout", server.base_url))
+        .await;
+
+    assert!(alice.me(&server).await.is_some());
+    let response = alice
+        .post(&format!("{}/api/auth/refresh", server.base_url))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn rotated_refresh_token_revokes_the_session() {
+    let server = TestServer::start(&[]).await;
+
+    let mut alice = Browser::new();
+    alice.login(&server, "mock", "alice").await;
+    let rotated = alice.cookies["refresh_token"].clone();
+
+    let response = alice
+        .post(&format!("{}/api/auth/refresh", server.base_url))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_ne!(alice.cookies["refresh_token"], rotated);
+
+    // Right after the rotation it could still be another tab refreshing at the same time
+    let response = with_refresh_token(&rotated)
+        .post(&format!("{}/api/auth/refresh", server.base_url))
+        .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert!(alice.me(&server).await.is_some());
+
+    sqlx::query("UPDATE sessions SET rotated_at = NOW() - INTERVAL '1 hour'")
+        .execute(&server.db)
+        .await
+        .unwrap();
+
+    let response = with_refresh_token(&rotated)
+        .post(&format!("{}/api/auth/refresh", server.base_url))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    assert!(alice.me(&server).await.is_none());
+
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn concurrent_refreshes_keep_the_session() {
+    let server = TestServer::start(&[]).await;
+
+    let mut alice = Browser::new();
+    alice.login(&server, "mock", "alice").await;
+
+    // Two tabs of the same browser sending the same refresh token at once
+    let refresh_url = format!("{}/api/auth/refresh", server.base_url);
+    let mut first_tab = Browser::new();
+    first_tab.cookies = alice.cookies.clone();
+    let mut second_tab = Browser::new();
+    second_tab.cookies = alice.cookies.clone();
+
+    let (first, second) = tokio::join!(first_tab.post(&refresh_url), second_tab.post(&refresh_url));
+
+    let mut statuses = [first.status(), second.status()];
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::NO_CONTENT, StatusCode::CONFLICT]);
+
+    // The loser keeps its cookies, the browser has the winner's by the time it retries
+    let winner = match first.status() {
+        StatusCode::NO_CONTENT => first_tab,
+        _ => second_tab,
+    };
+    let mut retry = Browser::new();
+    retry.cookies = winner.cookies;
+
+    assert!(retry.me(&server).await.is_some());
+    let response = retry.post(&refresh_url).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let (revoked,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM sessions WHERE revoked_at IS NOT NULL")
+            .fetch_one(&server.db)
+            .await
+            .unwrap();
+    assert_eq!(revoked, 0);
+
+    server.stop().await;
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::server::{Browser, TestServer};
+use reqwest::StatusCode;
+
+#[tokio::test]
+#[ignore = "needs a Postgres in TEST_DB_CONN_STRING"]
+async fn images_over_the_max_width_are_unprocessable_without_downscaling() {
+    let server =
+        TestServer::start(&[("IMAGE_DOWNSCALE", "false"), ("IMAGE_MAX_WIDTH", "64")]).await;
+
+    let mut browser = Browser::new();
+    browser.login(&server, "mock", "alice").await;
+
+    for (kind, file_data) in [
+        ("png", common::png(65, 16)),
+        ("gif", common::gif(65, 16, 2)),
+    ] {
+        let response = browser.upload(&server, file_data).await;
+        assert_eq!(
+            response.status(),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "{}",
+            kind
+        );
+        assert_eq!(
+            response.text().await.unwrap(),
+            "Image exceeds 64x8192",
+            "{}",
+            kind
+        );
+    }
+
+    // Right at the limit they go through
+    for file_data in [common::png(64, 16), common::gif(64, 16, 2)] {
+        let response = browser.upload(&server, file_data).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    assert_eq!(server.count("memes").await, 2);
+
+    server.stop().await;
+}